@@ -1,30 +1,80 @@
-use crate::app::config::Config;
+use crate::app::clock::{Clock, ClockMode};
+use crate::app::config::{parse_duration, Config};
 use projectm::core::ProjectM;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use sdl3::video::{GLProfile, WindowPos};
-use std::convert::TryInto;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
 pub mod audio;
+pub mod clock;
+pub mod command;
 pub mod config;
+pub mod headless;
+#[cfg(unix)]
+pub mod ipc;
+pub mod keybindings;
 pub mod main_loop;
+pub mod midi;
+pub mod osc;
+pub mod osd;
 pub mod playlist;
+pub mod reload;
+pub mod render;
+pub mod stats;
 pub mod video;
+pub mod watcher;
 
 pub type ProjectMWrapped = Rc<ProjectM>;
 
 /// Application state
 pub struct App {
     pm: ProjectMWrapped,
-    playlist: projectm::playlist::Playlist,
+    playlist: playlist::Playlist,
+    clock: Clock,
+    rng: StdRng,
+    /// Options of presets loaded from playlist files, by path.
+    preset_options: HashMap<PathBuf, playlist::PresetOptions>,
+    /// When the current preset started playing.
+    preset_started: Duration,
+    /// Whether presets switched this frame were switched on request, by the
+    /// user or a command.
+    switch_requested: bool,
+    favorites: playlist::PresetList,
+    blacklist: playlist::PresetList,
+    /// The whole playlist while only favorites are played.
+    all_presets: Option<Vec<PathBuf>>,
+    stats: stats::Stats,
     sdl_context: sdl3::Sdl,
     window: sdl3::video::Window,
     config: config::Config,
-    audio: audio::Audio,
+    keybindings: keybindings::Keybindings,
+    audio_subsystem: sdl3::AudioSubsystem,
+    audio_source: Box<dyn audio::AudioSource>,
+    frame_writer: Option<headless::FrameWriter>,
+    #[cfg(unix)]
+    ipc_server: Option<ipc::IpcServer>,
+    osc_listener: Option<osc::OscListener>,
+    midi_input: Option<midi::MidiInput>,
+    midi_map: Option<midi::MidiMap>,
+    osd: Option<osd::Osd>,
+    config_reloader: Option<reload::ConfigReloader>,
+    preset_watcher: Option<playlist::PresetWatcher>,
     _gl_context: sdl3::video::GLContext,
 }
 
 impl App {
     pub fn new(config: Config) -> Self {
+        let headless = config.headless.unwrap_or(false);
+        if headless {
+            // render offscreen, without a display or window system
+            sdl3::hint::set("SDL_VIDEO_DRIVER", "offscreen");
+        }
+
         // setup sdl
         let sdl_context = sdl3::init().unwrap();
         // print SDL version
@@ -45,9 +95,14 @@ impl App {
         assert_eq!(gl_attr.context_version(), (3, 3));
 
         // create window
-        let mut window = video_subsystem
-            .window("ProjectM", 1024, 768)
-            .opengl()
+        let width = config.width.unwrap_or(1024);
+        let height = config.height.unwrap_or(768);
+        let mut window_builder = video_subsystem.window("ProjectM", width, height);
+        window_builder.opengl();
+        if headless {
+            window_builder.hidden();
+        }
+        let mut window = window_builder
             .build()
             .expect("could not initialize video subsystem");
 
@@ -55,35 +110,158 @@ impl App {
         let gl_context = window.gl_create_context().unwrap();
         window.gl_make_current(&gl_context).unwrap();
 
+        // load GL functions for reading back frames
+        gl::load_with(|name| {
+            video_subsystem
+                .gl_get_proc_address(name)
+                .map_or(std::ptr::null(), |f| f as *const _)
+        });
+
+        // on-screen display, drawn over projectM's output
+        let osd_mode = if config.osd.unwrap_or(!headless) {
+            osd::OsdMode::Messages
+        } else {
+            osd::OsdMode::Off
+        };
+        let osd_duration = match parse_duration("osd_duration", config.osd_duration.unwrap_or(3.0))
+        {
+            Ok(duration) => duration,
+            Err(e) => {
+                println!("Warning: {}", e);
+                Duration::from_secs(3)
+            }
+        };
+        let osd = osd::Osd::new(osd_mode, osd_duration)
+            .map_err(|e| println!("Warning: OSD disabled: {}", e))
+            .ok();
+
         // initialize projectM
         let pm = Rc::new(ProjectM::create());
 
         // and a preset playlist
-        let playlist = projectm::playlist::Playlist::create(&pm);
-
-        // make window full-size
-        let primary_display = video_subsystem.get_primary_display().unwrap();
-        let display_bounds = primary_display.get_usable_bounds().unwrap();
-        window
-            .set_size(display_bounds.width(), display_bounds.height())
-            .unwrap();
-        window.set_position(WindowPos::Centered, WindowPos::Centered);
-        window
-            .set_display_mode(None)
-            .expect("could not set display mode");
+        let playlist = playlist::Playlist::create(&pm);
+
+        // favorites and blacklisted presets, kept between runs
+        let data_dir = config::data_dir();
+        if data_dir.is_none() {
+            println!("Warning: no data directory, favorites and blacklist won't be kept");
+        }
+        let favorites =
+            playlist::PresetList::load(data_dir.as_ref().map(|dir| dir.join("favorites.m3u")));
+        let blacklist =
+            playlist::PresetList::load(data_dir.as_ref().map(|dir| dir.join("blacklist.m3u")));
+
+        // play statistics, also kept between runs; headless and offline renders
+        // aren't watched by anyone, so they'd only distort the ratings
+        let stats = if headless {
+            stats::Stats::load(None).unwrap()
+        } else {
+            stats::Stats::load_default().unwrap_or_else(|e| {
+                println!("Warning: {}, statistics won't be kept", e);
+                stats::Stats::load(None).unwrap()
+            })
+        };
+
+        // frame clock; fixed-step runs always seed random preset selection so the order repeats
+        let fixed_step = config.fixed_step.unwrap_or(false);
+        let clock_mode = if fixed_step {
+            ClockMode::FixedStep
+        } else {
+            ClockMode::RealTime
+        };
+        let clock = Clock::new(clock_mode, config.frame_rate.unwrap_or(60));
+        let rng = match config.seed.or(fixed_step.then_some(0)) {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+
+        // make window full-size, unless a size was requested
+        if !headless && config.width.is_none() && config.height.is_none() {
+            let primary_display = video_subsystem.get_primary_display().unwrap();
+            let display_bounds = primary_display.get_usable_bounds().unwrap();
+            window
+                .set_size(display_bounds.width(), display_bounds.height())
+                .unwrap();
+            window.set_position(WindowPos::Centered, WindowPos::Centered);
+            window
+                .set_display_mode(None)
+                .expect("could not set display mode");
+        }
 
         // initialize audio
-        let audio = audio::Audio::new(&sdl_context, Rc::clone(&pm));
+        let audio_subsystem = sdl_context.audio().unwrap();
+        println!(
+            "Using audio driver: {}",
+            audio_subsystem.current_audio_driver()
+        );
+        let audio_source = audio::create_source(&audio_subsystem, &config);
+
+        // listen for commands from other programs
+        #[cfg(unix)]
+        let ipc_server = config.ipc_socket.as_ref().and_then(|path| {
+            ipc::IpcServer::bind(path)
+                .map_err(|e| println!("Warning: IPC disabled: {}", e))
+                .ok()
+        });
+        #[cfg(not(unix))]
+        if config.ipc_socket.is_some() {
+            println!("Warning: IPC sockets are only supported on Unix");
+        }
+
+        let osc_listener = config.osc_port.and_then(|port| {
+            osc::OscListener::bind(
+                config.osc_bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                port,
+                &config.osc_addresses.clone().unwrap_or_default(),
+            )
+            .map_err(|e| println!("Warning: OSC disabled: {}", e))
+            .ok()
+        });
+
+        let keybindings =
+            keybindings::Keybindings::new(&config.keybindings.clone().unwrap_or_default());
+
+        let midi_input = config.midi_input.as_ref().and_then(|port| {
+            midi::MidiInput::open(port)
+                .map_err(|e| println!("Warning: MIDI disabled: {}", e))
+                .ok()
+        });
+        let midi_map = midi_input.as_ref().map(|_| {
+            midi::MidiMap::new(
+                &config.midi_notes.clone().unwrap_or_default(),
+                &config.midi_controllers.clone().unwrap_or_default(),
+            )
+        });
 
         println!("Application initialized with configuration:\n{}", config);
 
         Self {
             pm,
             playlist,
+            clock,
+            rng,
+            preset_options: HashMap::new(),
+            preset_started: Duration::ZERO,
+            switch_requested: false,
+            favorites,
+            blacklist,
+            all_presets: None,
+            stats,
             sdl_context,
             window,
             config,
-            audio,
+            keybindings,
+            audio_subsystem,
+            audio_source,
+            frame_writer: None,
+            #[cfg(unix)]
+            ipc_server,
+            osc_listener,
+            midi_input,
+            midi_map,
+            osd,
+            config_reloader: None,
+            preset_watcher: None,
             _gl_context: gl_context, // keep this around to keep the context alive
         }
     }
@@ -91,9 +269,13 @@ impl App {
     pub fn init(&mut self) {
         // load config
         self.apply_config(&self.config);
+        self.apply_playlist_options();
+        if self.config.watch_presets.unwrap_or(false) {
+            self.watch_presets();
+        }
 
         // initialize audio
-        self.audio.init(self.get_frame_rate());
+        self.open_audio_source();
 
         self.update_projectm_window_size();
     }
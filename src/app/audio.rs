@@ -1,189 +1,138 @@
+use std::time::Duration;
+
 use projectm::core::ProjectM;
-use sdl3::audio::{AudioDevice, AudioDeviceID, AudioSpec, AudioStreamOwner};
-
-use super::config::FrameRate;
-use super::ProjectMWrapped;
-
-type SampleFormat = f32; // Format of audio samples
-const CHANNELS: u32 = 2; // Number of audio channels
-
-pub struct Audio {
-    audio_subsystem: sdl3::AudioSubsystem,
-    recording_stream: Option<Box<AudioStreamOwner>>,
-    is_capturing: bool,
-    frame_rate: Option<FrameRate>,
-    projectm: ProjectMWrapped,
-    current_device_id: Option<AudioDeviceID>,
-    current_device_name: Option<String>, // Store device name for comparison
+
+use crate::app::config::Config;
+use crate::app::App;
+
+pub mod capture;
+pub mod file;
+pub mod synth;
+
+pub const CHANNELS: u32 = 2; // Number of audio channels
+const SAMPLE_RATE: u32 = 44100; // Sample rate to capture and generate audio at
+
+/// A source of audio samples to visualize.
+///
+/// Samples are interleaved stereo `f32` frames.
+pub trait AudioSource {
+    /// Start producing samples.
+    fn open(&mut self) -> Result<(), String>;
+
+    /// Read available samples into `buf`, returning the number of samples read.
+    /// Returns 0 once no more samples are available for this frame.
+    ///
+    /// `now` is the app clock time of the frame being rendered. Sources that
+    /// produce audio themselves generate samples up to this time.
+    fn read(&mut self, buf: &mut [f32], now: Duration) -> usize;
+
+    /// Human-readable name of the source, e.g. the capture device name.
+    fn describe(&self) -> String;
+
+    /// Stop producing samples and release any devices.
+    fn close(&mut self);
 }
 
-impl Audio {
-    pub fn new(sdl_context: &sdl3::Sdl, projectm: ProjectMWrapped) -> Self {
-        let audio_subsystem = sdl_context.audio().unwrap();
-        println!(
-            "Using audio driver: {}",
-            audio_subsystem.current_audio_driver()
-        );
+/// Counts how many sample frames are due for sources that produce audio at the app clock's pace.
+struct SampleClock {
+    started: Option<Duration>,
+    sample_rate: u32,
+    frames_fed: u64,
+}
 
+impl SampleClock {
+    fn new(sample_rate: u32) -> Self {
         Self {
-            is_capturing: false,
-            audio_subsystem,
-            frame_rate: None,
-            current_device_id: None,
-            current_device_name: None,
-            recording_stream: None,
-            projectm,
+            started: None,
+            sample_rate,
+            frames_fed: 0,
         }
     }
 
-    pub fn init(&mut self, frame_rate: FrameRate) {
-        self.list_devices();
-
-        self.frame_rate = Some(frame_rate);
+    /// Number of frames that should have been produced by `now` but weren't yet.
+    /// The clock starts at the time of the first call.
+    fn frames_due(&mut self, now: Duration) -> u64 {
+        let started = *self.started.get_or_insert(now);
+        let elapsed = now.saturating_sub(started);
+        let frames_total = elapsed.as_nanos() * self.sample_rate as u128 / 1_000_000_000;
+        (frames_total as u64).saturating_sub(self.frames_fed)
+    }
 
-        #[cfg(not(feature = "dummy_audio"))]
-        self.begin_audio_recording(None);
+    fn advance(&mut self, frames: u64) {
+        self.frames_fed += frames;
     }
+}
 
-    pub fn list_devices(&self) {
-        let devices = self.get_device_list();
+/// Build the audio source selected by the configuration.
+pub fn create_source(
+    audio_subsystem: &sdl3::AudioSubsystem,
+    config: &Config,
+) -> Box<dyn AudioSource> {
+    // An audio file takes precedence over any other source
+    if let Some(path) = &config.audio_file {
+        return Box::new(file::FileSource::new(audio_subsystem, path));
+    }
 
-        println!("Audio Devices:");
-        for device in devices {
-            println!(
-                " - {} [{}]",
-                device.name().unwrap_or_else(|_| "unknown".to_string()),
-                device.id().value()
-            );
+    match config.audio_source.as_deref() {
+        None | Some("capture") => {}
+        Some("dummy") | Some("synth") => {
+            return Box::new(synth::SynthSource::new(synth::SynthSpec::default()))
         }
+        Some(other) => match other.strip_prefix("synth:").map(synth::SynthSpec::parse) {
+            Some(Ok(spec)) => return Box::new(synth::SynthSource::new(spec)),
+            Some(Err(e)) => println!("Invalid synth audio source '{}': {}", other, e),
+            None => println!("Unknown audio source '{}', capturing instead", other),
+        },
     }
 
-    /// Start capturing audio from device_id.
-    pub fn begin_audio_recording(&mut self, device_id: Option<AudioDeviceID>) {
-        // Stop capturing from current stream/device
-        self.stop_audio_recording();
-
-        let sample_rate: u32 = 44100;
-
-        let desired_spec = AudioSpec {
-            freq: Some(sample_rate as i32),
-            channels: Some(CHANNELS.try_into().unwrap()),
-            format: Some(sdl3::audio::AudioFormat::f32_sys()), // Assuming F32SYS is the correct format
-        };
-
-        let device = match device_id {
-            Some(id) => AudioDevice::new(id, self.audio_subsystem.clone()),
-            None => self.get_default_recording_device(),
-        };
-
-        let audio_stream = match device.open_device_stream(Some(&desired_spec)) {
-            Ok(stream) => stream,
-            Err(e) => {
-                println!("Failed to open audio stream: {}", e);
-                return;
-            }
-        };
-
-        println!("Capturing audio from device {:?}", audio_stream.device_id());
+    // Resolve the requested input device, falling back to the default
+    let device_id = config
+        .audio_input
+        .as_deref()
+        .and_then(|input| capture::find_device(audio_subsystem, input));
+    Box::new(capture::CaptureSource::new(audio_subsystem, device_id))
+}
 
-        // Get the actual device ID and name from the stream
-        let actual_device_id = audio_stream.device_id();
-        let actual_device_name = audio_stream.device_name();
+impl App {
+    /// Start the configured audio source, falling back to the default capture device.
+    pub fn open_audio_source(&mut self) {
+        capture::list_devices(&self.audio_subsystem);
 
-        if actual_device_id.is_none() {
+        if let Err(e) = self.audio_source.open() {
             println!(
-                "Failed to get device ID from audio stream: {:?}",
-                audio_stream.device_name()
+                "Failed to open audio source {}: {}",
+                self.audio_source.describe(),
+                e
             );
-            return;
+            self.set_audio_source(Box::new(capture::CaptureSource::new(
+                &self.audio_subsystem,
+                None,
+            )));
         }
-
-        let actual_device_id = actual_device_id.unwrap();
-        let actual_device_name = actual_device_name.unwrap_or_else(|| "unknown".to_string());
-
-        // Start capturing
-        if let Err(e) = audio_stream.resume() {
-            println!("Failed to start audio capture: {}", e);
-            return;
-        }
-
-        // Take ownership of the stream and store device information
-        self.recording_stream = Some(Box::new(audio_stream));
-        self.current_device_id = Some(actual_device_id);
-        self.current_device_name = Some(actual_device_name);
-        self.is_capturing = true;
     }
 
-    fn get_default_recording_device(&self) -> AudioDevice {
-        self.audio_subsystem.default_recording_device()
-    }
+    /// Close the current audio source and start `source` instead.
+    pub fn set_audio_source(&mut self, mut source: Box<dyn AudioSource>) {
+        self.audio_source.close();
 
-    /// Select a new audio device and start capturing audio from it.
-    pub fn open_next_device(&mut self) {
-        let current_device_name = self.recording_device_name();
-
-        let device_list = self.get_device_list();
-
-        println!("Device list: {:?}", device_list);
-        println!("Current device name: {:?}", current_device_name);
-
-        // Find the index of the current device by name
-        let current_device_index = current_device_name.as_ref().and_then(|name| {
-            device_list
-                .iter()
-                .position(|d| d.name() == Ok(name.to_string()))
-        });
-
-        let current_device_index = current_device_index.unwrap_or_else(|| {
-            println!("Current device not found in device list. Starting from the first device.");
-            0
-        });
-
-        // Select next device index
-        let next_device_index = (current_device_index + 1) % device_list.len();
-        let next_device_id = device_list[next_device_index];
-
-        println!(
-            "Switching from device '{}' to '{}'",
-            current_device_name.unwrap_or_else(|| "unknown".to_string()),
-            next_device_id
-                .name()
-                .unwrap_or_else(|_| "unknown".to_string())
-        );
-
-        // Start capturing from next device
-        self.begin_audio_recording(Some(next_device_id));
+        if let Err(e) = source.open() {
+            println!("Failed to open audio source {}: {}", source.describe(), e);
+        }
+        self.audio_source = source;
+        self.osd_message(format!("Audio: {}", self.audio_source.describe()));
     }
 
-    pub fn stop_audio_recording(&mut self) {
-        if let Some(stream) = self.recording_stream.take() {
-            // Retrieve the device name before dropping the stream
-            let current_device_name = stream
-                .device_name()
-                .unwrap_or_else(|| "unknown".to_string());
-
-            println!("Stopping audio capture for device {}", current_device_name);
-
-            // The recording device will be closed when the stream is dropped
-            self.is_capturing = false;
-            drop(stream);
-        }
+    /// Select the next capture device and start capturing audio from it.
+    pub fn open_next_audio_device(&mut self) {
+        let current_device_name = self.audio_source.describe();
+        let source =
+            capture::CaptureSource::next_device(&self.audio_subsystem, &current_device_name);
+        self.set_audio_source(Box::new(source));
     }
 
-    /// Read all available audio samples from the recording stream and feed them to ProjectM.
+    /// Read all available audio samples from the audio source and feed them to ProjectM.
     /// This method should be called once per frame.
     pub fn process_frame_samples(&mut self) {
-        if !self.is_capturing || self.recording_stream.is_none() {
-            return;
-        }
-
-        let stream = self.recording_stream.as_mut().unwrap();
-        let available_bytes = stream.available_bytes();
-        if available_bytes.is_err() || available_bytes.unwrap() == 0 {
-            // nothing to read
-            return;
-        }
-
         // Retrieve the maximum number of PCM samples ProjectM can handle
         let max_samples: usize = ProjectM::pcm_get_max_samples()
             .try_into()
@@ -191,40 +140,17 @@ impl Audio {
 
         // Allocate the sample buffer once to reuse in the loop
         let mut sample_buf = vec![0.0f32; max_samples];
+        let now = self.clock.now();
 
-        // Start the loop to read and process samples
         loop {
-            // Attempt to read samples into the buffer
-            match stream.read_f32_samples(&mut sample_buf) {
-                Ok(samples_read) => {
-                    if samples_read == 0 {
-                        // No more data to read; exit the loop
-                        break;
-                    }
-
-                    // Add the read samples to ProjectM for processing
-                    self.projectm
-                        .pcm_add_float(&sample_buf[..samples_read], CHANNELS);
-                }
-                Err(e) => {
-                    // Handle any read errors
-                    println!("Failed to read audio samples: {}", e);
-                    break; // Exit the loop on error
-                }
+            let samples_read = self.audio_source.read(&mut sample_buf, now);
+            if samples_read == 0 {
+                // No more data to read; exit the loop
+                break;
             }
-        }
-    }
-
-    fn get_device_list(&self) -> Vec<AudioDeviceID> {
-        self.audio_subsystem
-            .audio_recording_device_ids()
-            .unwrap_or_else(|e| {
-                println!("Failed to get audio device list: {}", e);
-                Vec::new()
-            })
-    }
 
-    pub fn recording_device_name(&self) -> Option<String> {
-        self.current_device_name.clone()
+            // Add the read samples to ProjectM for processing
+            self.pm.pcm_add_float(&sample_buf[..samples_read], CHANNELS);
+        }
     }
 }
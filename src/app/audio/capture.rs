@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use sdl3::audio::{AudioDevice, AudioDeviceID, AudioSpec, AudioStreamOwner};
+
+use super::{AudioSource, CHANNELS, SAMPLE_RATE};
+
+/// Captures audio from an SDL recording device.
+pub struct CaptureSource {
+    audio_subsystem: sdl3::AudioSubsystem,
+    device_id: Option<AudioDeviceID>,
+    recording_stream: Option<AudioStreamOwner>,
+    device_name: Option<String>,
+}
+
+impl CaptureSource {
+    /// Capture from `device_id`, or from the default recording device if `None`.
+    pub fn new(audio_subsystem: &sdl3::AudioSubsystem, device_id: Option<AudioDeviceID>) -> Self {
+        Self {
+            audio_subsystem: audio_subsystem.clone(),
+            device_id,
+            recording_stream: None,
+            device_name: None,
+        }
+    }
+
+    /// Capture from the device after the current one in the device list.
+    pub fn next_device(audio_subsystem: &sdl3::AudioSubsystem, current_device_name: &str) -> Self {
+        let device_list = device_list(audio_subsystem);
+
+        println!("Device list: {:?}", device_list);
+        println!("Current device name: {:?}", current_device_name);
+
+        if device_list.is_empty() {
+            println!("No audio capture devices available.");
+            return Self::new(audio_subsystem, None);
+        }
+
+        // Find the index of the current device by name
+        let current_device_index = device_list
+            .iter()
+            .position(|d| d.name() == Ok(current_device_name.to_string()))
+            .unwrap_or_else(|| {
+                println!(
+                    "Current device not found in device list. Starting from the first device."
+                );
+                0
+            });
+
+        // Select next device index
+        let next_device_index = (current_device_index + 1) % device_list.len();
+        let next_device_id = device_list[next_device_index];
+
+        println!(
+            "Switching from device '{}' to '{}'",
+            current_device_name,
+            next_device_id
+                .name()
+                .unwrap_or_else(|_| "unknown".to_string())
+        );
+
+        Self::new(audio_subsystem, Some(next_device_id))
+    }
+}
+
+impl AudioSource for CaptureSource {
+    /// Start capturing audio from the device.
+    fn open(&mut self) -> Result<(), String> {
+        // Stop capturing from current stream/device
+        self.close();
+
+        let desired_spec = AudioSpec {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(CHANNELS.try_into().unwrap()),
+            format: Some(sdl3::audio::AudioFormat::f32_sys()),
+        };
+
+        let device = match self.device_id {
+            Some(id) => AudioDevice::new(id, self.audio_subsystem.clone()),
+            None => self.audio_subsystem.default_recording_device(),
+        };
+
+        let audio_stream = device
+            .open_device_stream(Some(&desired_spec))
+            .map_err(|e| format!("Failed to open audio stream: {}", e))?;
+
+        println!("Capturing audio from device {:?}", audio_stream.device_id());
+
+        // Get the actual device name from the stream
+        if audio_stream.device_id().is_none() {
+            return Err(format!(
+                "Failed to get device ID from audio stream: {:?}",
+                audio_stream.device_name()
+            ));
+        }
+        let actual_device_name = audio_stream
+            .device_name()
+            .unwrap_or_else(|| "unknown".to_string());
+
+        // Start capturing
+        audio_stream
+            .resume()
+            .map_err(|e| format!("Failed to start audio capture: {}", e))?;
+
+        // Take ownership of the stream and store device information
+        self.recording_stream = Some(audio_stream);
+        self.device_name = Some(actual_device_name);
+
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [f32], _now: Duration) -> usize {
+        let stream = match self.recording_stream.as_mut() {
+            Some(stream) => stream,
+            None => return 0,
+        };
+
+        match stream.available_bytes() {
+            Ok(available_bytes) if available_bytes > 0 => {}
+            // nothing to read
+            _ => return 0,
+        }
+
+        stream.read_f32_samples(buf).unwrap_or_else(|e| {
+            println!("Failed to read audio samples: {}", e);
+            0
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.device_name
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    fn close(&mut self) {
+        if let Some(stream) = self.recording_stream.take() {
+            // Retrieve the device name before dropping the stream
+            let current_device_name = stream
+                .device_name()
+                .unwrap_or_else(|| "unknown".to_string());
+
+            println!("Stopping audio capture for device {}", current_device_name);
+
+            // The recording device will be closed when the stream is dropped
+            drop(stream);
+        }
+    }
+}
+
+pub fn list_devices(audio_subsystem: &sdl3::AudioSubsystem) {
+    let devices = device_list(audio_subsystem);
+
+    println!("Audio Devices:");
+    for (index, device) in devices.iter().enumerate() {
+        println!(
+            " {}: {} [{}]",
+            index,
+            device.name().unwrap_or_else(|_| "unknown".to_string()),
+            device.id().value()
+        );
+    }
+}
+
+/// Look up a recording device by index, name, or name pattern.
+/// Returns `None` (the default device) if no device matches.
+pub fn find_device(audio_subsystem: &sdl3::AudioSubsystem, input: &str) -> Option<AudioDeviceID> {
+    if input.eq_ignore_ascii_case("default") {
+        return None;
+    }
+
+    let device_list = device_list(audio_subsystem);
+    let names: Vec<String> = device_list
+        .iter()
+        .map(|d| d.name().unwrap_or_default())
+        .collect();
+
+    match match_device(&names, input) {
+        Some(index) => {
+            println!("Selected audio input '{}' for '{}'", names[index], input);
+            Some(device_list[index])
+        }
+        None => {
+            println!(
+                "Warning: audio input '{}' not found, falling back to the default recording device",
+                input
+            );
+            None
+        }
+    }
+}
+
+fn device_list(audio_subsystem: &sdl3::AudioSubsystem) -> Vec<AudioDeviceID> {
+    audio_subsystem
+        .audio_recording_device_ids()
+        .unwrap_or_else(|e| {
+            println!("Failed to get audio device list: {}", e);
+            Vec::new()
+        })
+}
+
+/// Find the device matching `input` in a list of device names.
+///
+/// `input` may be an index into the list, an exact device name, a glob
+/// pattern (`*`, `?`), or a substring of a device name. Name matching is
+/// case-insensitive and the first match wins.
+pub fn match_device(names: &[String], input: &str) -> Option<usize> {
+    if let Ok(index) = input.parse::<usize>() {
+        return (index < names.len()).then_some(index);
+    }
+
+    if let Some(index) = names.iter().position(|name| name == input) {
+        return Some(index);
+    }
+
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    if let Ok(pattern) = glob::Pattern::new(input) {
+        if let Some(index) = names
+            .iter()
+            .position(|name| pattern.matches_with(name, options))
+        {
+            return Some(index);
+        }
+    }
+
+    let input = input.to_lowercase();
+    names
+        .iter()
+        .position(|name| name.to_lowercase().contains(&input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_device;
+
+    #[test]
+    fn test_match_device() {
+        let names = vec![
+            "Built-in Microphone".to_string(),
+            "Monitor of Built-in Audio".to_string(),
+            "USB Audio Interface".to_string(),
+        ];
+
+        assert_eq!(match_device(&names, "1"), Some(1));
+        assert_eq!(match_device(&names, "3"), None);
+        assert_eq!(match_device(&names, "USB Audio Interface"), Some(2));
+        assert_eq!(match_device(&names, "monitor*"), Some(1));
+        assert_eq!(match_device(&names, "usb"), Some(2));
+        assert_eq!(match_device(&names, "line in"), None);
+    }
+}
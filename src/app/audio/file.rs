@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sdl3::audio::{AudioSpec, AudioStreamOwner};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::{AudioSource, SampleClock, CHANNELS, SAMPLE_RATE};
+
+/// Decodes an audio file into interleaved stereo samples.
+pub struct FileReader {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+}
+
+impl FileReader {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+        // Let the file extension help guess the container format
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| format!("unsupported audio file {}: {}", path.display(), e))?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| format!("no audio track in {}", path.display()))?;
+        let track_id = track.id;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| format!("unknown sample rate in {}", path.display()))?;
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| format!("unsupported codec in {}: {}", path.display(), e))?;
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            sample_rate,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Decode the next packet into interleaved stereo samples.
+    /// Returns `None` at the end of the file.
+    pub fn next_samples(&mut self) -> Option<Vec<f32>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(_)) => return None,
+                Err(e) => {
+                    println!("Failed to read audio file: {}", e);
+                    return None;
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Corrupt packets can be skipped
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => {
+                    println!("Failed to decode audio file: {}", e);
+                    return None;
+                }
+            };
+
+            let spec = *decoded.spec();
+            let mut sample_buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            sample_buf.copy_interleaved_ref(decoded);
+
+            return Some(to_stereo(sample_buf.samples(), spec.channels.count()));
+        }
+    }
+}
+
+/// Convert interleaved samples with any channel count to interleaved stereo.
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32> {
+    match channels {
+        2 => samples.to_vec(),
+        0 => Vec::new(),
+        1 => samples.iter().flat_map(|&s| [s, s]).collect(),
+        // Keep the front left/right channels
+        _ => samples
+            .chunks_exact(channels)
+            .flat_map(|frame| [frame[0], frame[1]])
+            .collect(),
+    }
+}
+
+/// Plays an audio file and feeds it to projectM at real-time pace.
+///
+/// The file loops when it reaches the end.
+pub struct FileSource {
+    audio_subsystem: sdl3::AudioSubsystem,
+    path: PathBuf,
+    reader: Option<FileReader>,
+    playback_stream: Option<AudioStreamOwner>,
+    pending: VecDeque<f32>,
+    clock: SampleClock,
+}
+
+impl FileSource {
+    pub fn new(audio_subsystem: &sdl3::AudioSubsystem, path: &Path) -> Self {
+        Self {
+            audio_subsystem: audio_subsystem.clone(),
+            path: path.to_path_buf(),
+            reader: None,
+            playback_stream: None,
+            pending: VecDeque::new(),
+            clock: SampleClock::new(SAMPLE_RATE),
+        }
+    }
+
+    /// Decode the next chunk of the file, rewinding at the end.
+    fn decode_more(&mut self) -> bool {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return false,
+        };
+        if let Some(samples) = reader.next_samples() {
+            self.pending.extend(samples);
+            return true;
+        }
+
+        println!("Reached end of {}, starting over", self.path.display());
+        match FileReader::open(&self.path) {
+            Ok(mut reader) => {
+                let samples = reader.next_samples();
+                self.reader = Some(reader);
+                match samples {
+                    Some(samples) => {
+                        self.pending.extend(samples);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Err(e) => {
+                println!("Failed to reopen audio file: {}", e);
+                false
+            }
+        }
+    }
+}
+
+impl AudioSource for FileSource {
+    /// Open the file for decoding and start playing it on the default playback device.
+    fn open(&mut self) -> Result<(), String> {
+        self.close();
+
+        let reader = FileReader::open(&self.path)?;
+
+        let spec = AudioSpec {
+            freq: Some(reader.sample_rate as i32),
+            channels: Some(CHANNELS as i32),
+            format: Some(sdl3::audio::AudioFormat::f32_sys()),
+        };
+
+        // Playback is optional; we can still visualize without an output device
+        self.playback_stream = match self
+            .audio_subsystem
+            .default_playback_device()
+            .open_device_stream(Some(&spec))
+        {
+            Ok(stream) => match stream.resume() {
+                Ok(()) => Some(stream),
+                Err(e) => {
+                    println!("Failed to start audio playback: {}", e);
+                    None
+                }
+            },
+            Err(e) => {
+                println!("Failed to open audio playback stream: {}", e);
+                None
+            }
+        };
+
+        println!(
+            "Playing audio file {} ({} Hz)",
+            self.path.display(),
+            reader.sample_rate
+        );
+
+        self.clock = SampleClock::new(reader.sample_rate);
+        self.reader = Some(reader);
+
+        Ok(())
+    }
+
+    /// Read the samples that are due by now, queueing them for playback too.
+    fn read(&mut self, buf: &mut [f32], now: Duration) -> usize {
+        let frames_due = self.clock.frames_due(now);
+        if frames_due == 0 {
+            return 0;
+        }
+        if self.pending.is_empty() && !self.decode_more() {
+            return 0;
+        }
+
+        let count = (frames_due as usize * CHANNELS as usize)
+            .min(self.pending.len())
+            .min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..count)) {
+            *dst = src;
+        }
+
+        if let Some(stream) = &self.playback_stream {
+            if let Err(e) = stream.put_data_f32(&buf[..count]) {
+                println!("Failed to queue audio playback: {}", e);
+            }
+        }
+
+        self.clock.advance((count / CHANNELS as usize) as u64);
+        count
+    }
+
+    fn describe(&self) -> String {
+        format!("file: {}", self.path.display())
+    }
+
+    fn close(&mut self) {
+        if self.reader.take().is_some() {
+            println!("Stopping playback of {}", self.path.display());
+        }
+        // The playback device will be closed when the stream is dropped
+        self.playback_stream = None;
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,234 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+
+use super::{AudioSource, SampleClock, CHANNELS, SAMPLE_RATE};
+
+/// Signal shapes the synthesizer can generate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    /// Logarithmic sine sweep from 20 Hz to 20 kHz.
+    Sweep,
+    /// White noise.
+    White,
+    /// Pink (1/f) noise.
+    Pink,
+    /// Kick drum hits on every beat.
+    Kick,
+    /// Kick drum with a randomly pitched tone on every beat.
+    Random,
+}
+
+/// Parameters of the synthesized signal, parsed from `synth:<waveform>[,key=value...]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SynthSpec {
+    pub waveform: Waveform,
+    /// Tempo of kick drum hits, in beats per minute.
+    pub bpm: f32,
+    /// Duration of one sine sweep (seconds).
+    pub period: f32,
+    /// Seed for noise and random tones.
+    pub seed: u64,
+}
+
+impl Default for SynthSpec {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Kick,
+            bpm: 120.0,
+            period: 10.0,
+            seed: 0,
+        }
+    }
+}
+
+impl SynthSpec {
+    /// Parse the part of an audio source after `synth:`, e.g. `kick,bpm=128`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',').map(str::trim);
+
+        let waveform = match parts.next().unwrap_or("") {
+            "" | "kick" => Waveform::Kick,
+            "sweep" | "sine" => Waveform::Sweep,
+            "white" | "noise" => Waveform::White,
+            "pink" => Waveform::Pink,
+            "random" => Waveform::Random,
+            other => return Err(format!("unknown synth waveform '{}'", other)),
+        };
+        let mut synth = SynthSpec {
+            waveform,
+            ..Default::default()
+        };
+
+        for option in parts {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("invalid synth option '{}'", option))?;
+            let invalid = || format!("invalid value for synth option '{}'", option);
+            match key {
+                "bpm" => synth.bpm = value.parse().map_err(|_| invalid())?,
+                "period" => synth.period = value.parse().map_err(|_| invalid())?,
+                "seed" => synth.seed = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown synth option '{}'", key)),
+            }
+        }
+
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if !(positive(synth.bpm) && positive(synth.period)) {
+            return Err("synth bpm and period must be positive numbers".to_string());
+        }
+
+        Ok(synth)
+    }
+}
+
+/// Generates a deterministic test signal, for running without any audio device.
+pub struct SynthSource {
+    spec: SynthSpec,
+    clock: SampleClock,
+    rng: StdRng,
+    /// Index of the next sample frame to generate.
+    frame: u64,
+    phase: f32,
+    tone_phase: f32,
+    tone_freq: f32,
+    pink: [f32; 7],
+}
+
+impl SynthSource {
+    pub fn new(spec: SynthSpec) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(spec.seed),
+            spec,
+            clock: SampleClock::new(SAMPLE_RATE),
+            frame: 0,
+            phase: 0.0,
+            tone_phase: 0.0,
+            tone_freq: 440.0,
+            pink: [0.0; 7],
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let sample_rate = SAMPLE_RATE as f32;
+        self.frame += 1;
+
+        match self.spec.waveform {
+            Waveform::Sweep => {
+                // from the frame count rather than the time in seconds, which
+                // loses precision as a long session goes on
+                let period_frames = self.period_frames();
+                let progress = ((self.frame - 1) % period_frames) as f32 / period_frames as f32;
+                let freq = 20.0 * 1000.0f32.powf(progress);
+                self.phase = (self.phase + TAU * freq / sample_rate) % TAU;
+                0.5 * self.phase.sin()
+            }
+            Waveform::White => self.white(),
+            Waveform::Pink => self.pink(),
+            Waveform::Kick => self.kick(),
+            Waveform::Random => {
+                let beat_frames = self.beat_frames();
+                if (self.frame - 1) % beat_frames == 0 {
+                    self.tone_freq = self.rng.random_range(110.0..880.0);
+                }
+                let beat_time = ((self.frame - 1) % beat_frames) as f32 / sample_rate;
+                self.tone_phase = (self.tone_phase + TAU * self.tone_freq / sample_rate) % TAU;
+                let tone = 0.3 * self.tone_phase.sin() * (-beat_time * 3.0).exp();
+                self.kick() + tone
+            }
+        }
+    }
+
+    fn beat_frames(&self) -> u64 {
+        ((60.0 / self.spec.bpm as f64 * SAMPLE_RATE as f64) as u64).max(1)
+    }
+
+    fn period_frames(&self) -> u64 {
+        ((self.spec.period as f64 * SAMPLE_RATE as f64) as u64).max(1)
+    }
+
+    fn white(&mut self) -> f32 {
+        self.rng.random_range(-1.0..1.0)
+    }
+
+    /// Pink noise using Paul Kellet's filter.
+    fn pink(&mut self) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+
+    /// Decaying sine with a falling pitch, restarted on every beat.
+    fn kick(&mut self) -> f32 {
+        let beat_frames = self.beat_frames();
+        let beat_time = ((self.frame - 1) % beat_frames) as f32 / SAMPLE_RATE as f32;
+        if beat_time == 0.0 {
+            self.phase = 0.0;
+        }
+        let freq = 50.0 + 100.0 * (-beat_time * 30.0).exp();
+        self.phase = (self.phase + TAU * freq / SAMPLE_RATE as f32) % TAU;
+        0.9 * self.phase.sin() * (-beat_time * 8.0).exp()
+    }
+}
+
+impl AudioSource for SynthSource {
+    fn open(&mut self) -> Result<(), String> {
+        *self = Self::new(self.spec.clone());
+        println!("Generating synthetic audio: {:?}", self.spec);
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [f32], now: Duration) -> usize {
+        let frames = (self.clock.frames_due(now) as usize).min(buf.len() / CHANNELS as usize);
+        let count = frames * CHANNELS as usize;
+
+        for frame in buf[..count].chunks_exact_mut(CHANNELS as usize) {
+            frame.fill(self.next_sample());
+        }
+
+        self.clock.advance(frames as u64);
+        count
+    }
+
+    fn describe(&self) -> String {
+        format!("synth: {:?}", self.spec.waveform).to_lowercase()
+    }
+
+    fn close(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SynthSpec, Waveform};
+
+    #[test]
+    fn test_parse_synth_spec() {
+        assert_eq!(SynthSpec::parse("").unwrap(), SynthSpec::default());
+
+        let spec = SynthSpec::parse("kick,bpm=128").unwrap();
+        assert_eq!(spec.waveform, Waveform::Kick);
+        assert_eq!(spec.bpm, 128.0);
+
+        let spec = SynthSpec::parse("pink, seed=42").unwrap();
+        assert_eq!(spec.waveform, Waveform::Pink);
+        assert_eq!(spec.seed, 42);
+
+        assert!(SynthSpec::parse("square").is_err());
+        assert!(SynthSpec::parse("sweep,period").is_err());
+        assert!(SynthSpec::parse("kick,bpm=0").is_err());
+        assert!(SynthSpec::parse("kick,bpm=nan").is_err());
+        assert!(SynthSpec::parse("kick,bpm=inf").is_err());
+        assert!(SynthSpec::parse("sweep,period=nan").is_err());
+        assert!(SynthSpec::parse("sweep,period=-inf").is_err());
+    }
+}
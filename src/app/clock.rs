@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+/// How the frame clock advances.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockMode {
+    /// Follow wall-clock time.
+    RealTime,
+    /// Advance exactly one frame interval per frame, however long frames take.
+    FixedStep,
+    /// Advance one frame interval per frame like [`ClockMode::FixedStep`], but
+    /// never sleep, for offline rendering as fast as frames can be drawn.
+    Unpaced,
+}
+
+/// Frame timing for the render loop.
+///
+/// `now()` is the time of the current frame, which audio sources use to decide
+/// how many samples to produce. In fixed-step and unpaced modes it only depends
+/// on the frame number, so the same input always produces the same audio per
+/// frame.
+pub struct Clock {
+    mode: ClockMode,
+    frame_rate: u32,
+    started: Instant,
+    frame_started: Instant,
+    frame: u64,
+}
+
+impl Clock {
+    /// A clock for `frame_rate` frames per second. A frame rate of 0 runs real
+    /// time unthrottled; fixed-step mode falls back to 60 fps. Unpaced clocks
+    /// need a frame rate above 0.
+    pub fn new(mode: ClockMode, frame_rate: u32) -> Self {
+        let frame_rate = match mode {
+            ClockMode::FixedStep if frame_rate == 0 => 60,
+            _ => frame_rate,
+        };
+        let now = Instant::now();
+        Self {
+            mode,
+            frame_rate,
+            started: now,
+            frame_started: now,
+            frame: 0,
+        }
+    }
+
+    /// Number of frames completed so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Time of the current frame since the clock started.
+    pub fn now(&self) -> Duration {
+        match self.mode {
+            ClockMode::RealTime => self.started.elapsed(),
+            ClockMode::FixedStep | ClockMode::Unpaced => self.frame_time(self.frame),
+        }
+    }
+
+    /// Finish the current frame, sleeping as needed to hold the frame rate.
+    ///
+    /// Fixed-step frames are still paced to real time, since libprojectM animates
+    /// presets and transitions from its own wall-clock timer. Unpaced frames
+    /// never wait.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+
+        if self.frame_rate > 0 && self.mode != ClockMode::Unpaced {
+            let deadline = match self.mode {
+                ClockMode::RealTime => {
+                    self.frame_started + Duration::from_secs(1) / self.frame_rate
+                }
+                ClockMode::FixedStep | ClockMode::Unpaced => {
+                    self.started + self.frame_time(self.frame)
+                }
+            };
+            if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                // sleep the remaining frame time
+                std::thread::sleep(wait);
+            }
+        }
+
+        self.frame_started = Instant::now();
+    }
+
+    fn frame_time(&self, frame: u64) -> Duration {
+        Duration::from_nanos(frame * 1_000_000_000 / self.frame_rate as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ClockMode};
+    use std::time::Duration;
+
+    #[test]
+    fn test_fixed_step_clock() {
+        let mut clock = Clock::new(ClockMode::FixedStep, 1000);
+        assert_eq!(clock.now(), Duration::ZERO);
+
+        for _ in 0..5 {
+            clock.end_frame();
+        }
+        assert_eq!(clock.frame(), 5);
+        assert_eq!(clock.now(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_unpaced_clock() {
+        // an hour of frames at 1 fps, which would take an hour if paced
+        let mut clock = Clock::new(ClockMode::Unpaced, 1);
+        for _ in 0..3600 {
+            clock.end_frame();
+        }
+        assert_eq!(clock.now(), Duration::from_secs(3600));
+    }
+}
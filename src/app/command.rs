@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::app::App;
+
+/// A command for the running visualizer, sent by IPC or OSC clients.
+///
+/// Deserializes from JSON objects naming the command, e.g.
+/// `{"command": "set", "key": "beat_sensitivity", "value": 1.4}`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Switch to the next preset with a blended transition lasting the soft
+    /// cut duration, like timed switches.
+    Next,
+    /// Switch to the previous preset with a blended transition.
+    Prev,
+    /// Switch to a random preset with a blended transition.
+    Random,
+    /// Switch to the next preset immediately, without a transition.
+    HardCut,
+    /// Load a preset file, or switch to a playlist index.
+    Load {
+        preset: Option<PathBuf>,
+        index: Option<u32>,
+    },
+    /// Lock or unlock the current preset; toggles if `locked` is missing.
+    Lock {
+        locked: Option<bool>,
+    },
+    Set {
+        key: String,
+        value: Value,
+    },
+    Fullscreen,
+    /// Write the playlist to `path`, or the configured playlist file.
+    SavePlaylist {
+        path: Option<PathBuf>,
+    },
+    Status,
+}
+
+impl App {
+    /// Run `command`, returning its result or `Null` if it has none.
+    pub fn run_command(&mut self, command: Command) -> Result<Value, String> {
+        match command {
+            Command::Next => {
+                self.switch_requested = true;
+                self.playlist.play_next(false);
+            }
+            Command::Prev => {
+                self.switch_requested = true;
+                self.playlist.play_prev(false);
+            }
+            Command::Random => {
+                self.switch_requested = true;
+                self.play_random(false);
+            }
+            Command::HardCut => {
+                self.switch_requested = true;
+                self.playlist.play_next(true);
+            }
+            Command::Load {
+                index: Some(index), ..
+            } => {
+                if index >= self.playlist.len() {
+                    return Err(format!("no preset at index {}", index));
+                }
+                self.switch_requested = true;
+                self.playlist.set_position(index, true);
+            }
+            Command::Load {
+                preset: Some(preset),
+                ..
+            } => {
+                if !preset.is_file() {
+                    return Err(format!("preset not found: {}", preset.display()));
+                }
+                // projectM expects a NUL-terminated path
+                self.pm
+                    .load_preset_file(&format!("{}\0", preset.display()), false);
+            }
+            Command::Load { .. } => return Err("load needs a preset or an index".to_string()),
+            Command::Lock { locked } => {
+                let locked = locked.unwrap_or(!self.pm.get_preset_locked());
+                self.set_preset_locked(locked);
+                return Ok(json!({ "locked": locked }));
+            }
+            Command::Set { key, value } => {
+                self.set_value(&key, &value)?;
+                self.osd_message(format!("{}: {}", key, value));
+            }
+            Command::Fullscreen => self.toggle_fullscreen(),
+            Command::SavePlaylist { path } => {
+                let path = self.save_playlist(path.as_deref())?;
+                return Ok(json!({ "path": path }));
+            }
+            Command::Status => return Ok(self.status()),
+        }
+
+        Ok(Value::Null)
+    }
+
+    fn set_value(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        let number = || {
+            value
+                .as_f64()
+                .ok_or_else(|| format!("{} must be a number", key))
+        };
+
+        match key {
+            "beat_sensitivity" => {
+                let beat_sensitivity = number()? as f32;
+                self.pm.set_beat_sensitivity(beat_sensitivity);
+                self.config.beat_sensitivity = Some(beat_sensitivity);
+            }
+            "preset_duration" => {
+                let preset_duration = number()?;
+                self.config.preset_duration = Some(preset_duration);
+                self.apply_preset_duration();
+            }
+            "shuffle" => {
+                let shuffle = value
+                    .as_bool()
+                    .ok_or_else(|| format!("{} must be a boolean", key))?;
+                self.set_shuffle(shuffle);
+            }
+            "favorites_only" => {
+                let favorites_only = value
+                    .as_bool()
+                    .ok_or_else(|| format!("{} must be a boolean", key))?;
+                self.set_favorites_only(favorites_only);
+            }
+            "hard_cut_enabled" => {
+                let hard_cut_enabled = value
+                    .as_bool()
+                    .ok_or_else(|| format!("{} must be a boolean", key))?;
+                self.pm.set_hard_cut_enabled(hard_cut_enabled);
+                self.config.hard_cut_enabled = Some(hard_cut_enabled);
+            }
+            "hard_cut_duration" => {
+                let hard_cut_duration = number()?;
+                self.pm.set_hard_cut_duration(hard_cut_duration);
+                self.config.hard_cut_duration = Some(hard_cut_duration);
+            }
+            "hard_cut_sensitivity" => {
+                let hard_cut_sensitivity = number()? as f32;
+                self.pm.set_hard_cut_sensitivity(hard_cut_sensitivity);
+                self.config.hard_cut_sensitivity = Some(hard_cut_sensitivity);
+            }
+            "soft_cut_duration" => {
+                let soft_cut_duration = number()?;
+                self.pm.set_soft_cut_duration(soft_cut_duration);
+                self.config.soft_cut_duration = Some(soft_cut_duration);
+            }
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+
+        Ok(())
+    }
+
+    fn status(&self) -> Value {
+        json!({
+            "preset": self.playlist.current(),
+            "position": self.playlist.position(),
+            "playlist_size": self.playlist.len(),
+            "shuffle": self.playlist.shuffle(),
+            "favorite": self.playlist.current().filter(|p| self.is_favorite(p)).is_some(),
+            "favorites_only": self.all_presets.is_some(),
+            "locked": self.pm.get_preset_locked(),
+            "beat_sensitivity": self.pm.get_beat_sensitivity(),
+            "preset_duration": self.preset_duration(),
+            "fullscreen": self.window.fullscreen_state() == sdl3::video::FullscreenType::True,
+            "audio_source": self.audio_source.describe(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_command() {
+        let parse = |value| Command::deserialize(&value).ok();
+
+        assert_eq!(parse(json!({ "command": "next" })), Some(Command::Next));
+        assert_eq!(
+            parse(json!({ "command": "set", "key": "beat_sensitivity", "value": 1.4 })),
+            Some(Command::Set {
+                key: "beat_sensitivity".to_string(),
+                value: json!(1.4),
+            })
+        );
+        assert_eq!(
+            parse(json!({ "command": "load", "preset": "a.milk" })),
+            Some(Command::Load {
+                preset: Some("a.milk".into()),
+                index: None,
+            })
+        );
+        assert_eq!(
+            parse(json!({ "command": "lock", "id": 7 })),
+            Some(Command::Lock { locked: None })
+        );
+        assert_eq!(
+            parse(json!({ "command": "save_playlist" })),
+            Some(Command::SavePlaylist { path: None })
+        );
+        assert_eq!(parse(json!({ "command": "explode" })), None);
+    }
+}
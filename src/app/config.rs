@@ -1,28 +1,161 @@
+use crate::app::playlist::preset_dirs;
 use crate::app::App;
 use core::fmt;
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub type FrameRate = u32;
 
-const RESOURCE_DIR_DEFAULT: &str = "/usr/local/share/projectM";
-
 /// Configuration for the application
 /// Parameters are defined here: https://github.com/projectM-visualizer/projectm/blob/master/src/api/include/projectM-4/parameters.h
+#[derive(Clone, PartialEq)]
 pub struct Config {
     /// Frame rate to render at. Defaults to 60.
     pub frame_rate: Option<FrameRate>,
 
-    /// Path to the preset directory. Defaults to /usr/local/share/projectM/presets
-    pub preset_path: Option<PathBuf>,
+    /// Preset directories, preset files and glob patterns matching either, added
+    /// to the playlist in order. Defaults to the `presets` directories found in
+    /// [`resource_dirs`].
+    pub preset_path: Option<Vec<PathBuf>>,
 
-    /// Path to the texture directory. Defaults to /usr/local/share/projectM/textures
-    pub texture_path: Option<PathBuf>,
+    /// Texture directories, searched in order before the `textures` directories
+    /// next to and inside each preset directory. Defaults to the `textures`
+    /// directories found in [`resource_dirs`].
+    pub texture_path: Option<Vec<PathBuf>>,
 
     /// How sensitive the beat detection is. 1.0 is default.
     pub beat_sensitivity: Option<f32>,
 
     /// How long to play a preset before switching to a new one (seconds).
     pub preset_duration: Option<f64>,
+
+    /// Where audio comes from: `capture` (default) or a generated test signal,
+    /// `synth:<waveform>[,bpm=<bpm>][,period=<seconds>][,seed=<seed>]` with waveform
+    /// one of `sweep`, `white`, `pink`, `kick` or `random`.
+    pub audio_source: Option<String>,
+
+    /// Audio capture device to use: a device name, a name pattern, or an index
+    /// into the device list. Defaults to the system default recording device.
+    pub audio_input: Option<String>,
+
+    /// Audio file to play and visualize instead of capturing from a device.
+    pub audio_file: Option<PathBuf>,
+
+    /// Window width. Defaults to the full display width, or 1024 when headless.
+    pub width: Option<u32>,
+
+    /// Window height. Defaults to the full display height, or 768 when headless.
+    pub height: Option<u32>,
+
+    /// Render offscreen and write frames to `output_dir` instead of showing a window.
+    pub headless: Option<bool>,
+
+    /// Number of frames to render when headless, at least 1. Defaults to 300.
+    pub frames: Option<u32>,
+
+    /// Directory to write headless frames to. Defaults to `frames`.
+    pub output_dir: Option<PathBuf>,
+
+    /// Advance audio time by exactly one frame interval per frame, so runs with
+    /// the same input get the same audio per frame and preset order. Presets
+    /// still animate on libprojectM's wall clock, so frames don't repeat
+    /// exactly. Defaults to following wall-clock time.
+    pub fixed_step: Option<bool>,
+
+    /// Seed for random preset selection. Fixed-step runs default to 0.
+    pub seed: Option<u64>,
+
+    /// Unix domain socket to listen on for line-delimited JSON commands. Disabled by default.
+    pub ipc_socket: Option<PathBuf>,
+
+    /// UDP port to listen on for OSC messages. Disabled by default.
+    pub osc_port: Option<u16>,
+
+    /// Address to listen on for OSC messages. Defaults to 127.0.0.1, so only
+    /// local programs can send commands.
+    pub osc_bind: Option<IpAddr>,
+
+    /// OSC addresses mapped to actions, on top of the default `/projectm/...` addresses.
+    /// An action is `next`, `prev`, `random`, `hard_cut`, `fullscreen`, `lock`, `index`
+    /// or `set <setting>`; the first message argument supplies values.
+    pub osc_addresses: Option<HashMap<String, String>>,
+
+    /// MIDI input port to take commands from: a port name, a name pattern, an index
+    /// into the port list, or `virtual` to create a port other programs can connect to.
+    pub midi_input: Option<String>,
+
+    /// Print incoming MIDI messages, to help set up the MIDI mappings.
+    pub midi_learn: Option<bool>,
+
+    /// MIDI note numbers mapped to `next`, `prev`, `random`, `hard_cut`, `lock` or
+    /// `fullscreen`, on top of the defaults (notes 36-39).
+    pub midi_notes: Option<HashMap<String, String>>,
+
+    /// MIDI controller numbers mapped to `<setting> [<min> <max>]`, scaling the
+    /// controller value onto the range; on top of the defaults (controllers 1, 20, 21).
+    pub midi_controllers: Option<HashMap<String, String>>,
+
+    /// Key chords such as `Space` or `Ctrl+Shift+N` mapped to `next_preset`, `prev_preset`,
+    /// `random_preset`, `toggle_lock`, `toggle_shuffle`, `toggle_fullscreen`,
+    /// `next_audio_device`, `toggle_osd`, `save_playlist`, `toggle_favorite`,
+    /// `blacklist_preset`, `toggle_favorites_only`, `quit` or `none`, on top of the
+    /// default bindings.
+    pub keybindings: Option<HashMap<String, String>>,
+
+    /// Show the on-screen display with messages such as preset switches. Enabled by
+    /// default unless rendering headless.
+    pub osd: Option<bool>,
+
+    /// Seconds on-screen messages stay visible, including fading out.
+    pub osd_duration: Option<f64>,
+
+    /// Allow switching presets without a transition on strong beats.
+    pub hard_cut_enabled: Option<bool>,
+
+    /// Minimum seconds a preset plays before a hard cut can switch away from it.
+    pub hard_cut_duration: Option<f64>,
+
+    /// Beat intensity needed for a hard cut.
+    pub hard_cut_sensitivity: Option<f32>,
+
+    /// Seconds a blended transition between presets takes.
+    pub soft_cut_duration: Option<f64>,
+
+    /// Correct shapes and waveforms in presets for the window's aspect ratio.
+    pub aspect_correction: Option<bool>,
+
+    /// Size of the per-pixel equation mesh as `<width>x<height>`, e.g. `48x32`. Larger meshes
+    /// look smoother but take more time to render.
+    pub mesh_size: Option<String>,
+
+    /// Spread of the randomized preset durations around the preset duration.
+    pub easter_egg: Option<f32>,
+
+    /// Pick the next preset at random instead of going through the playlist in order.
+    pub shuffle: Option<bool>,
+
+    /// Order of the presets in the playlist: `name`, `path`, `mtime` (oldest first) or
+    /// `random`, shuffled once using `seed`. Presets are in filesystem order by default.
+    pub playlist_order: Option<String>,
+
+    /// Playlist file to load presets from, ahead of those from `preset_path`, and to save the
+    /// playlist to. `.json` files also keep per-preset durations and favorites, anything else
+    /// is read and written as M3U.
+    pub playlist: Option<PathBuf>,
+
+    /// Only play favorite presets, those marked with the favorite key or in a JSON playlist file.
+    pub favorites_only: Option<bool>,
+
+    /// Pick random presets weighted by their rating from the play statistics, favoring presets that
+    /// were rarely skipped. With `shuffle` this includes timed switches, but not hard cuts, which
+    /// libprojectM makes itself.
+    pub weighted_random: Option<bool>,
+
+    /// Watch the preset directories: reload the current preset when its file changes, and add and
+    /// remove presets as their files appear and disappear.
+    pub watch_presets: Option<bool>,
 }
 
 impl fmt::Display for Config {
@@ -30,20 +163,34 @@ impl fmt::Display for Config {
         writeln!(
             f,
             "  Preset path: {}",
-            self.preset_path.as_ref().map_or("None".to_string(), |p| p
-                .canonicalize()
-                .unwrap_or_else(|_| p.clone())
-                .display()
-                .to_string())
+            self.preset_path
+                .as_ref()
+                .filter(|paths| !paths.is_empty())
+                .map_or("None".to_string(), |paths| paths
+                    .iter()
+                    .map(|p| p
+                        .canonicalize()
+                        .unwrap_or_else(|_| p.clone())
+                        .display()
+                        .to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "))
         )?;
         writeln!(
             f,
             "  Texture path: {}",
-            self.texture_path.as_ref().map_or("None".to_string(), |p| p
-                .canonicalize()
-                .unwrap_or_else(|_| p.clone())
-                .display()
-                .to_string())
+            self.texture_path
+                .as_ref()
+                .filter(|paths| !paths.is_empty())
+                .map_or("None".to_string(), |paths| paths
+                    .iter()
+                    .map(|p| p
+                        .canonicalize()
+                        .unwrap_or_else(|_| p.clone())
+                        .display()
+                        .to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "))
         )?;
         writeln!(
             f,
@@ -57,11 +204,132 @@ impl fmt::Display for Config {
             self.beat_sensitivity
                 .map_or("Not specified".to_string(), |s| s.to_string())
         )?;
-        write!(
+        writeln!(
             f,
             "  Preset Duration: {}",
             self.preset_duration
                 .map_or("Not specified".to_string(), |d| d.to_string())
+        )?;
+        writeln!(
+            f,
+            "  Hard Cuts: {}",
+            match self.hard_cut_enabled {
+                Some(true) => "Enabled",
+                Some(false) => "Disabled",
+                None => "Not specified",
+            }
+        )?;
+        writeln!(
+            f,
+            "  Soft Cut Duration: {}",
+            self.soft_cut_duration
+                .map_or("Not specified".to_string(), |d| d.to_string())
+        )?;
+        writeln!(
+            f,
+            "  Mesh Size: {}",
+            self.mesh_size.as_deref().unwrap_or("Not specified")
+        )?;
+        writeln!(
+            f,
+            "  Audio Source: {}",
+            self.audio_source.as_deref().unwrap_or("capture")
+        )?;
+        writeln!(
+            f,
+            "  Audio Input: {}",
+            self.audio_input.as_deref().unwrap_or("Default")
+        )?;
+        writeln!(
+            f,
+            "  Audio File: {}",
+            self.audio_file
+                .as_ref()
+                .map_or("None".to_string(), |p| p.display().to_string())
+        )?;
+        writeln!(
+            f,
+            "  Clock: {}",
+            if self.fixed_step.unwrap_or(false) {
+                "Fixed step"
+            } else {
+                "Real time"
+            }
+        )?;
+        writeln!(
+            f,
+            "  Seed: {}",
+            self.seed.map_or("Random".to_string(), |s| s.to_string())
+        )?;
+        writeln!(
+            f,
+            "  Window Size: {}x{}",
+            self.width.map_or("auto".to_string(), |w| w.to_string()),
+            self.height.map_or("auto".to_string(), |h| h.to_string())
+        )?;
+        writeln!(
+            f,
+            "  IPC Socket: {}",
+            self.ipc_socket
+                .as_ref()
+                .map_or("None".to_string(), |p| p.display().to_string())
+        )?;
+        writeln!(
+            f,
+            "  OSC Port: {}",
+            self.osc_port.map_or("None".to_string(), |p| p.to_string())
+        )?;
+        writeln!(
+            f,
+            "  OSC Bind: {}",
+            self.osc_bind.map_or("None".to_string(), |a| a.to_string())
+        )?;
+        writeln!(
+            f,
+            "  MIDI Input: {}",
+            self.midi_input.as_deref().unwrap_or("None")
+        )?;
+        writeln!(
+            f,
+            "  OSD: {}",
+            self.osd.map_or("Default".to_string(), |o| o.to_string())
+        )?;
+        writeln!(
+            f,
+            "  Playlist: {} order{}{}",
+            self.playlist_order.as_deref().unwrap_or("filesystem"),
+            if self.shuffle.unwrap_or(false) {
+                ", shuffled"
+            } else {
+                ""
+            },
+            if self.favorites_only.unwrap_or(false) {
+                ", favorites only"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            f,
+            "  Playlist File: {}",
+            self.playlist
+                .as_ref()
+                .map_or("None".to_string(), |p| p.display().to_string())
+        )?;
+        write!(
+            f,
+            "  Headless: {}",
+            if self.headless.unwrap_or(false) {
+                format!(
+                    "{} frames to {}",
+                    self.frames.unwrap_or(300),
+                    self.output_dir
+                        .as_ref()
+                        .map_or("frames".to_string(), |p| p.display().to_string())
+                )
+            } else {
+                "No".to_string()
+            }
         )
     }
 }
@@ -79,30 +347,93 @@ fn default_resource_dir() -> std::path::PathBuf {
         .join("Resources")
 }
 
-#[cfg(not(target_os = "macos"))]
-fn default_resource_dir() -> std::path::PathBuf {
-    // On Linux, Windows, etc., do as you wish
-    "/usr/local/share/projectM".into()
+/// Directory for what the visualizer keeps between runs, such as the
+/// favorites: `projectm` in the user's data directory, e.g.
+/// `$XDG_DATA_HOME/projectm` on Linux.
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("projectm"))
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        #[cfg(target_os = "macos")]
-        let resource_dir = default_resource_dir(); // points to .app/Contents/Resources
+/// Directories that may hold presets and textures, in order: the user's
+/// [`data_dir`], then the app bundle's resources on macOS, or `projectM` in
+/// each of `$XDG_DATA_DIRS` (`/usr/local/share` and `/usr/share` by default)
+/// on other Unix systems.
+pub fn resource_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = data_dir().into_iter().collect();
 
-        #[cfg(not(target_os = "macos"))]
-        let resource_dir = std::path::PathBuf::from("/usr/local/share/projectM");
+    #[cfg(target_os = "macos")]
+    dirs.push(default_resource_dir()); // points to .app/Contents/Resources
 
-        // Construct paths
-        let presets_path = resource_dir.join("presets");
-        let textures_path = resource_dir.join("textures");
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let data_dirs = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+        dirs.extend(
+            std::env::split_paths(&data_dirs)
+                .filter(|dir| dir.is_absolute())
+                .map(|dir| dir.join("projectM")),
+        );
+    }
+
+    dirs
+}
+
+/// The existing `name` directories, e.g. `presets`, in the [`resource_dirs`].
+pub fn default_resource_paths(name: &str) -> Vec<PathBuf> {
+    resource_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let preset_paths = default_resource_paths("presets");
+        let texture_paths = default_resource_paths("textures");
 
         Self {
-            preset_path: presets_path.exists().then(|| presets_path),
-            texture_path: textures_path.exists().then(|| textures_path),
+            preset_path: (!preset_paths.is_empty()).then_some(preset_paths),
+            texture_path: (!texture_paths.is_empty()).then_some(texture_paths),
             frame_rate: Some(60),
             beat_sensitivity: Some(1.0),
             preset_duration: Some(10.0),
+            audio_source: None,
+            audio_input: None,
+            audio_file: None,
+            width: None,
+            height: None,
+            headless: None,
+            frames: None,
+            output_dir: None,
+            fixed_step: None,
+            seed: None,
+            ipc_socket: None,
+            osc_port: None,
+            osc_bind: None,
+            osc_addresses: None,
+            midi_input: None,
+            midi_learn: None,
+            midi_notes: None,
+            midi_controllers: None,
+            keybindings: None,
+            osd: None,
+            osd_duration: None,
+            hard_cut_enabled: None,
+            hard_cut_duration: None,
+            hard_cut_sensitivity: None,
+            soft_cut_duration: None,
+            aspect_correction: None,
+            mesh_size: None,
+            easter_egg: None,
+            shuffle: None,
+            playlist_order: None,
+            playlist: None,
+            favorites_only: None,
+            weighted_random: None,
+            watch_presets: None,
         }
     }
 }
@@ -117,15 +448,11 @@ impl App {
         }
 
         // load presets if provided
-        if let Some(preset_path) = &config.preset_path {
-            self.add_preset_path(preset_path);
+        if let Some(preset_paths) = &config.preset_path {
+            self.add_preset_paths(preset_paths);
         }
 
-        // load textures if provided
-        if let Some(texture_path) = &config.texture_path {
-            let paths = [texture_path.clone().into_os_string().into_string().unwrap()];
-            pm.set_texture_search_paths(&paths, 1);
-        }
+        self.apply_texture_paths(config);
 
         // set beat sensitivity if provided
         if let Some(beat_sensitivity) = config.beat_sensitivity {
@@ -137,11 +464,149 @@ impl App {
             pm.set_preset_duration(preset_duration);
         }
 
-        // set preset shuffle mode
-        // self.playlist.set_shuffle(true);
+        // hard cuts, switching presets without a transition on strong beats
+        if let Some(hard_cut_enabled) = config.hard_cut_enabled {
+            pm.set_hard_cut_enabled(hard_cut_enabled);
+        }
+        if let Some(hard_cut_duration) = config.hard_cut_duration {
+            pm.set_hard_cut_duration(hard_cut_duration);
+        }
+        if let Some(hard_cut_sensitivity) = config.hard_cut_sensitivity {
+            pm.set_hard_cut_sensitivity(hard_cut_sensitivity);
+        }
+
+        // set transition duration if provided
+        if let Some(soft_cut_duration) = config.soft_cut_duration {
+            pm.set_soft_cut_duration(soft_cut_duration);
+        }
+
+        if let Some(aspect_correction) = config.aspect_correction {
+            pm.set_aspect_correction(aspect_correction);
+        }
+
+        if let Some(mesh_size) = &config.mesh_size {
+            match parse_mesh_size(mesh_size) {
+                Ok((mesh_x, mesh_y)) => pm.set_mesh_size(mesh_x, mesh_y),
+                Err(e) => println!("Warning: {}", e),
+            }
+        }
+
+        if let Some(easter_egg) = config.easter_egg {
+            pm.set_easter_egg(easter_egg);
+        }
+    }
+
+    /// Search the configured texture directories, then those shipped with the
+    /// presets.
+    pub fn apply_texture_paths(&self, config: &Config) {
+        let texture_paths = texture_search_paths(
+            config.texture_path.as_deref().unwrap_or_default(),
+            config.preset_path.as_deref().unwrap_or_default(),
+        );
+        if !texture_paths.is_empty() {
+            println!("Texture search paths:");
+            for path in &texture_paths {
+                println!(" {}", path.display());
+            }
+            let paths: Vec<String> = texture_paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            self.pm.set_texture_search_paths(&paths, paths.len());
+        }
+    }
+}
+
+/// Directories to search for textures: the existing ones of `texture_paths`,
+/// followed by the `textures` directories inside and next to each directory
+/// holding presets from `preset_paths`. Missing texture paths are reported.
+fn texture_search_paths(texture_paths: &[PathBuf], preset_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut search_paths: Vec<PathBuf> = Vec::new();
+    let mut add = |path: PathBuf| {
+        let path = path.canonicalize().unwrap_or(path);
+        if !search_paths.contains(&path) {
+            search_paths.push(path);
+        }
+    };
+
+    for path in texture_paths {
+        if path.is_dir() {
+            add(path.clone());
+        } else {
+            println!("Warning: texture path not found: {}", path.display());
+        }
     }
 
-    pub fn get_frame_rate(&self) -> FrameRate {
-        self.pm.get_fps()
+    for preset_dir in preset_dirs(preset_paths) {
+        let candidates = [
+            Some(preset_dir.join("textures")),
+            preset_dir.parent().map(|parent| parent.join("textures")),
+        ];
+        for candidate in candidates.into_iter().flatten() {
+            if candidate.is_dir() {
+                add(candidate);
+            }
+        }
+    }
+
+    search_paths
+}
+
+/// Parse a mesh size given as `<width>x<height>`.
+pub fn parse_mesh_size(mesh_size: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid mesh size '{}', expected e.g. 48x32", mesh_size);
+    let (width, height) = mesh_size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a duration setting given in seconds. Negative durations count as 0.
+pub fn parse_duration(name: &str, seconds: f64) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(seconds.max(0.0))
+        .map_err(|e| format!("invalid {} {}: {}", name, seconds, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse_mesh_size() {
+        assert_eq!(parse_mesh_size("48x32"), Ok((48, 32)));
+        assert_eq!(parse_mesh_size("128X96"), Ok((128, 96)));
+        assert!(parse_mesh_size("48").is_err());
+        assert!(parse_mesh_size("0x32").is_err());
+        assert!(parse_mesh_size("axb").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            parse_duration("osd_duration", 1.5),
+            Ok(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_duration("osd_duration", -1.0), Ok(Duration::ZERO));
+        assert!(parse_duration("osd_duration", f64::INFINITY).is_err());
+        assert!(parse_duration("osd_duration", 1e30).is_err());
+    }
+
+    #[test]
+    fn test_texture_search_paths() {
+        let temp = TempDir::new();
+        let root = temp.path();
+        for dir in ["pack/presets", "pack/textures", "other/presets", "own"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("other/presets/a.milk"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let paths = texture_search_paths(
+            &[root.join("own"), root.join("missing")],
+            &[root.join("pack/presets"), root.join("other/presets/*.milk")],
+        );
+        assert_eq!(paths, vec![root.join("own"), root.join("pack/textures")]);
     }
 }
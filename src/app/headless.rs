@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::app::App;
+
+/// Writes rendered frames to numbered PNG files when rendering headless.
+pub struct FrameWriter {
+    output_dir: PathBuf,
+    frame_count: u32,
+    frames_written: u32,
+}
+
+impl FrameWriter {
+    pub fn new(output_dir: &Path, frame_count: u32) -> Result<Self, String> {
+        if frame_count == 0 {
+            return Err("frames must be at least 1".to_string());
+        }
+
+        fs::create_dir_all(output_dir).map_err(|e| {
+            format!(
+                "could not create output directory {}: {}",
+                output_dir.display(),
+                e
+            )
+        })?;
+
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            frame_count,
+            frames_written: 0,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frames_written >= self.frame_count
+    }
+
+    /// Write a frame of top-down RGBA pixels to the next numbered file.
+    fn write(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+        let path = self
+            .output_dir
+            .join(format!("frame_{:05}.png", self.frames_written));
+        write_png(&path, width, height, pixels)?;
+
+        self.frames_written += 1;
+        Ok(())
+    }
+}
+
+/// Read the current contents of the default framebuffer as top-down RGBA rows.
+pub fn read_pixels(width: u32, height: u32) -> Vec<u8> {
+    let row_len = width as usize * 4;
+    let mut pixels = vec![0u8; row_len * height as usize];
+
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr().cast(),
+        );
+    }
+
+    // OpenGL returns rows bottom-up
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks_exact(row_len).rev() {
+        flipped.extend_from_slice(row);
+    }
+    flipped
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+    let file = fs::File::create(path)
+        .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+impl App {
+    /// Write the frame that was just rendered, if rendering headless.
+    /// Returns `false` once all requested frames have been written.
+    pub fn write_headless_frame(&mut self) -> bool {
+        let (width, height) = self.window.size_in_pixels();
+
+        let frame_writer = match self.frame_writer.as_mut() {
+            Some(frame_writer) => frame_writer,
+            None => return true,
+        };
+
+        let pixels = read_pixels(width, height);
+        if let Err(e) = frame_writer.write(width, height, &pixels) {
+            println!("Failed to write frame: {}", e);
+            return false;
+        }
+
+        !frame_writer.is_done()
+    }
+}
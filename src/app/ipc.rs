@@ -0,0 +1,254 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::app::command::Command;
+use crate::app::App;
+
+/// Longest request line accepted. Clients sending longer lines are
+/// disconnected.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Most reply and event bytes queued for a client that isn't reading them.
+/// Clients falling further behind are disconnected.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+
+struct Client {
+    id: u64,
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    /// Output not yet written because the socket would block.
+    pending: Vec<u8>,
+}
+
+impl Client {
+    /// Queue `line` and write as much pending output as the socket takes.
+    /// Returns false if the client should be disconnected.
+    fn send(&mut self, line: &str) -> bool {
+        self.pending.extend_from_slice(line.as_bytes());
+        self.flush()
+    }
+
+    /// Write pending output until the socket would block. Returns false if the
+    /// client should be disconnected.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(count) => {
+                    self.pending.drain(..count);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+
+        if self.pending.len() > MAX_PENDING_OUTPUT {
+            println!(
+                "Warning: disconnecting IPC client {}, it has more than {} bytes of unread replies",
+                self.id, MAX_PENDING_OUTPUT
+            );
+            return false;
+        }
+        true
+    }
+}
+
+/// Unix domain socket accepting line-delimited JSON requests.
+///
+/// Every request gets a response line with `"ok": true` or `"ok": false` and
+/// an `"error"`. Events such as preset switches are sent to all clients as
+/// lines with an `"event"` member.
+pub struct IpcServer {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+    next_client_id: u64,
+}
+
+impl IpcServer {
+    /// Listen on `path`, replacing a stale socket left behind by a previous run.
+    pub fn bind(path: &Path) -> Result<Self, String> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket", path.display()));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!("{} is in use by another process", path.display()));
+            }
+            fs::remove_file(path)
+                .map_err(|e| format!("could not remove stale socket {}: {}", path.display(), e))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("could not listen on {}: {}", path.display(), e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        println!("Listening for commands on {}", path.display());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+            next_client_id: 0,
+        })
+    }
+
+    /// Accept new clients and return the complete lines received since the
+    /// last call, with the id of the client that sent them.
+    fn poll(&mut self) -> Vec<(u64, String)> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    id: self.next_client_id,
+                    stream,
+                    buffer: Vec::new(),
+                    pending: Vec::new(),
+                });
+                self.next_client_id += 1;
+            }
+        }
+
+        let mut lines = Vec::new();
+        self.clients.retain_mut(|client| {
+            if !client.flush() {
+                return false;
+            }
+
+            // stop reading once a request's worth is buffered, the rest is
+            // read on the next call
+            let mut chunk = [0u8; 4096];
+            let connected = loop {
+                if client.buffer.len() > MAX_REQUEST_SIZE {
+                    break true;
+                }
+                match client.stream.read(&mut chunk) {
+                    Ok(0) => break false,
+                    Ok(count) => client.buffer.extend_from_slice(&chunk[..count]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => break false,
+                }
+            };
+
+            while let Some(end) = client.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if !line.is_empty() {
+                    lines.push((client.id, line));
+                }
+            }
+
+            if client.buffer.len() > MAX_REQUEST_SIZE {
+                let error = format!("request longer than {} bytes", MAX_REQUEST_SIZE);
+                println!("Warning: disconnecting IPC client {}, {}", client.id, error);
+                client.send(&format!("{}\n", json!({ "ok": false, "error": error })));
+                return false;
+            }
+
+            connected
+        });
+
+        lines
+    }
+
+    fn send(&mut self, client_id: u64, message: &Value) {
+        let line = format!("{}\n", message);
+        self.clients
+            .retain_mut(|client| client.id != client_id || client.send(&line));
+    }
+
+    /// Send `message` to every connected client.
+    pub fn broadcast(&mut self, message: &Value) {
+        let line = format!("{}\n", message);
+        self.clients.retain_mut(|client| client.send(&line));
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl App {
+    /// Handle requests from IPC clients. Call once per frame.
+    pub fn process_ipc_requests(&mut self) {
+        let lines = match self.ipc_server.as_mut() {
+            Some(server) => server.poll(),
+            None => return,
+        };
+
+        for (client_id, line) in lines {
+            let response = self.handle_ipc_request(&line);
+            if let Some(server) = self.ipc_server.as_mut() {
+                server.send(client_id, &response);
+            }
+        }
+    }
+
+    fn handle_ipc_request(&mut self, line: &str) -> Value {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(e) => return json!({ "ok": false, "error": format!("invalid JSON: {}", e) }),
+        };
+        let id = message.get("id").cloned();
+
+        let mut response = match Command::deserialize(&message) {
+            Ok(command) => match self.run_command(command) {
+                Ok(Value::Null) => json!({ "ok": true }),
+                Ok(result) => json!({ "ok": true, "result": result }),
+                Err(e) => json!({ "ok": false, "error": e }),
+            },
+            Err(e) => json!({ "ok": false, "error": e.to_string() }),
+        };
+
+        if let Some(id) = id {
+            response["id"] = id;
+        }
+        response
+    }
+
+    /// Tell IPC clients about a preset switch.
+    pub fn send_ipc_preset_switched(&mut self, index: u32) {
+        let preset = self.playlist.item(index);
+        if let Some(server) = self.ipc_server.as_mut() {
+            server.broadcast(&json!({
+                "event": "preset_switched",
+                "index": index,
+                "preset": preset,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_bind() {
+        let temp = TempDir::new();
+
+        // a file that isn't a socket is left alone
+        let file = temp.path().join("notes.txt");
+        fs::write(&file, "notes").unwrap();
+        assert!(IpcServer::bind(&file).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "notes");
+
+        // a stale socket is replaced, a live one is not
+        let path = temp.path().join("projectm.sock");
+        drop(UnixListener::bind(&path).unwrap());
+        let server = IpcServer::bind(&path).unwrap();
+        assert!(IpcServer::bind(&path).is_err());
+        drop(server);
+        assert!(!path.exists());
+    }
+}
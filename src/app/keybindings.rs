@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl3::keyboard::{Keycode, Mod};
+
+use crate::app::App;
+
+/// Something a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NextPreset,
+    PrevPreset,
+    RandomPreset,
+    ToggleFullscreen,
+    NextAudioDevice,
+    ToggleLock,
+    ToggleShuffle,
+    ToggleOsd,
+    SavePlaylist,
+    ToggleFavorite,
+    BlacklistPreset,
+    ToggleFavoritesOnly,
+    Quit,
+}
+
+/// Action names as used in the `[keybindings]` table.
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("next_preset", Action::NextPreset),
+    ("prev_preset", Action::PrevPreset),
+    ("random_preset", Action::RandomPreset),
+    ("toggle_fullscreen", Action::ToggleFullscreen),
+    ("next_audio_device", Action::NextAudioDevice),
+    ("toggle_lock", Action::ToggleLock),
+    ("toggle_shuffle", Action::ToggleShuffle),
+    ("toggle_osd", Action::ToggleOsd),
+    ("save_playlist", Action::SavePlaylist),
+    ("toggle_favorite", Action::ToggleFavorite),
+    ("blacklist_preset", Action::BlacklistPreset),
+    ("toggle_favorites_only", Action::ToggleFavoritesOnly),
+    ("quit", Action::Quit),
+];
+
+/// Bindings for keys that aren't bound in the configuration.
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("N", Action::NextPreset),
+    ("Right", Action::NextPreset),
+    ("P", Action::PrevPreset),
+    ("Left", Action::PrevPreset),
+    ("R", Action::RandomPreset),
+    ("F", Action::ToggleFullscreen),
+    ("Ctrl+I", Action::NextAudioDevice),
+    ("Cmd+I", Action::NextAudioDevice),
+    ("L", Action::ToggleLock),
+    ("ScrollLock", Action::ToggleLock),
+    ("S", Action::ToggleShuffle),
+    ("O", Action::ToggleOsd),
+    ("Ctrl+S", Action::SavePlaylist),
+    ("Cmd+S", Action::SavePlaylist),
+    ("Ctrl+D", Action::ToggleFavorite),
+    ("Cmd+D", Action::ToggleFavorite),
+    ("Delete", Action::BlacklistPreset),
+    ("Ctrl+Shift+D", Action::ToggleFavoritesOnly),
+    ("Cmd+Shift+D", Action::ToggleFavoritesOnly),
+    ("Escape", Action::Quit),
+];
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        ACTION_NAMES
+            .iter()
+            .find(|(action_name, _)| action_name.eq_ignore_ascii_case(name))
+            .map(|(_, action)| *action)
+    }
+}
+
+/// A key together with the modifiers that have to be held down with it.
+/// Left and right modifier keys count as the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct KeyChord {
+    keycode: Keycode,
+    ctrl: bool,
+    shift: bool,
+    alt: bool,
+    gui: bool,
+}
+
+impl KeyChord {
+    /// Parse a chord like `Ctrl+Shift+N`, using SDL key names for the key.
+    fn parse(chord: &str) -> Result<Self, String> {
+        let (modifiers, key) = match chord.rsplit_once('+') {
+            // "+" on its own, or as the key after modifiers
+            Some((modifiers, "")) => (modifiers.strip_suffix('+').unwrap_or(modifiers), "+"),
+            Some((modifiers, key)) => (modifiers, key),
+            None => ("", chord),
+        };
+
+        // SDL looks single characters up in the current keymap, which turns
+        // "+" into the unshifted key it's typed with, "=" on US layouts
+        let keycode = match key.trim() {
+            "+" => Keycode::Plus,
+            key => Keycode::from_name(key).ok_or_else(|| format!("unknown key '{}'", key))?,
+        };
+        let mut parsed = Self {
+            keycode,
+            ctrl: false,
+            shift: false,
+            alt: false,
+            gui: false,
+        };
+
+        for modifier in modifiers.split('+').filter(|m| !m.trim().is_empty()) {
+            match modifier.trim().to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => parsed.ctrl = true,
+                "shift" => parsed.shift = true,
+                "alt" | "option" => parsed.alt = true,
+                "cmd" | "command" | "gui" | "super" | "meta" | "win" => parsed.gui = true,
+                _ => return Err(format!("unknown modifier '{}'", modifier.trim())),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// This chord and the chords that type the same character on common
+    /// layouts. `+` is `Shift+=` on US layouts, so a `+` chord also binds `=`
+    /// with and without Shift, like zoom shortcuts do, and the keypad `+`.
+    fn equivalents(self) -> Vec<Self> {
+        let mut chords = vec![self];
+        if self.keycode == Keycode::Plus {
+            let equals = Self {
+                keycode: Keycode::Equals,
+                ..self
+            };
+            chords.push(equals);
+            chords.push(Self {
+                shift: true,
+                ..equals
+            });
+            chords.push(Self {
+                keycode: Keycode::KpPlus,
+                ..self
+            });
+        }
+        chords
+    }
+
+    fn from_event(keycode: Keycode, keymod: Mod) -> Self {
+        Self {
+            keycode,
+            ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+            shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+            alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+            gui: keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD),
+        }
+    }
+}
+
+/// Maps key chords to actions.
+pub struct Keybindings {
+    bindings: HashMap<KeyChord, Action>,
+}
+
+impl Keybindings {
+    /// Build the bindings from the configured table, which maps chords to
+    /// action names on top of the defaults. `none` unbinds a chord. Invalid
+    /// entries are reported and skipped. Chords for `+` also bind the other
+    /// keys that type it, unless those are configured themselves.
+    pub fn new(configured: &HashMap<String, String>) -> Self {
+        let mut bindings: HashMap<KeyChord, Action> = DEFAULT_BINDINGS
+            .iter()
+            .map(|(chord, action)| (KeyChord::parse(chord).unwrap(), *action))
+            .collect();
+
+        let mut parsed = Vec::new();
+        for (chord, action_name) in configured {
+            match KeyChord::parse(chord) {
+                Ok(chord) => parsed.push((chord, action_name)),
+                Err(e) => println!("Warning: invalid key binding '{}': {}", chord, e),
+            }
+        }
+        let explicit: HashSet<KeyChord> = parsed.iter().map(|(chord, _)| *chord).collect();
+
+        for (chord, action_name) in parsed {
+            let action = if action_name.eq_ignore_ascii_case("none") {
+                None
+            } else {
+                match Action::from_name(action_name) {
+                    Some(action) => Some(action),
+                    None => {
+                        println!("Warning: unknown key binding action '{}'", action_name);
+                        continue;
+                    }
+                }
+            };
+
+            for (i, equivalent) in chord.equivalents().into_iter().enumerate() {
+                if i > 0 && explicit.contains(&equivalent) {
+                    continue;
+                }
+                match action {
+                    Some(action) => bindings.insert(equivalent, action),
+                    None => bindings.remove(&equivalent),
+                };
+            }
+        }
+
+        Self { bindings }
+    }
+
+    /// The action bound to `keycode` pressed with the modifiers in `keymod`.
+    pub fn action_for(&self, keycode: Keycode, keymod: Mod) -> Option<Action> {
+        self.bindings
+            .get(&KeyChord::from_event(keycode, keymod))
+            .copied()
+    }
+}
+
+impl App {
+    /// Perform `action`. Quitting is up to the caller.
+    pub fn run_action(&mut self, action: Action) {
+        match action {
+            Action::NextPreset => self.playlist_play_next(),
+            Action::PrevPreset => self.playlist_play_prev(),
+            Action::RandomPreset => self.playlist_play_random(),
+            Action::ToggleFullscreen => self.toggle_fullscreen(),
+            Action::NextAudioDevice => self.open_next_audio_device(),
+            Action::ToggleLock => self.set_preset_locked(!self.pm.get_preset_locked()),
+            Action::ToggleShuffle => self.set_shuffle(!self.playlist.shuffle()),
+            Action::ToggleOsd => self.toggle_osd(),
+            Action::SavePlaylist => {
+                if let Err(e) = self.save_playlist(None) {
+                    println!("Could not save playlist: {}", e);
+                    self.osd_message(format!("Could not save playlist: {}", e));
+                }
+            }
+            Action::ToggleFavorite => self.toggle_favorite(),
+            Action::BlacklistPreset => self.blacklist_current_preset(),
+            Action::ToggleFavoritesOnly => self.set_favorites_only(self.all_presets.is_none()),
+            Action::Quit => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        let chord = KeyChord::parse("Ctrl+Shift+N").unwrap();
+        assert_eq!(chord.keycode, Keycode::N);
+        assert!(chord.ctrl && chord.shift && !chord.alt && !chord.gui);
+
+        assert_eq!(KeyChord::parse("cmd+i"), KeyChord::parse("Gui+I"));
+        assert_eq!(KeyChord::parse("Space").unwrap().keycode, Keycode::Space);
+        assert_eq!(KeyChord::parse("Ctrl++").unwrap().keycode, Keycode::Plus);
+        assert!(KeyChord::parse("Hyper+N").is_err());
+        assert!(KeyChord::parse("NotAKey").is_err());
+    }
+
+    #[test]
+    fn test_keybindings() {
+        let configured = HashMap::from([
+            ("Space".to_string(), "next_preset".to_string()),
+            ("N".to_string(), "none".to_string()),
+            ("Ctrl+Q".to_string(), "quit".to_string()),
+            ("Ctrl++".to_string(), "random_preset".to_string()),
+            ("Ctrl+Shift+=".to_string(), "toggle_lock".to_string()),
+        ]);
+        let keybindings = Keybindings::new(&configured);
+
+        assert_eq!(
+            keybindings.action_for(Keycode::Space, Mod::NOMOD),
+            Some(Action::NextPreset)
+        );
+        assert_eq!(keybindings.action_for(Keycode::N, Mod::NOMOD), None);
+        assert_eq!(
+            keybindings.action_for(Keycode::Q, Mod::RCTRLMOD),
+            Some(Action::Quit)
+        );
+        // defaults stay bound, and lock keys don't get in the way
+        assert_eq!(
+            keybindings.action_for(Keycode::Right, Mod::NUMMOD),
+            Some(Action::NextPreset)
+        );
+        assert_eq!(
+            keybindings.action_for(Keycode::I, Mod::LGUIMOD),
+            Some(Action::NextAudioDevice)
+        );
+        assert_eq!(keybindings.action_for(Keycode::I, Mod::NOMOD), None);
+        // "+" is also bound where layouts put it, but explicit chords win
+        for (keycode, keymod) in [
+            (Keycode::Plus, Mod::LCTRLMOD),
+            (Keycode::Equals, Mod::LCTRLMOD),
+            (Keycode::KpPlus, Mod::LCTRLMOD | Mod::NUMMOD),
+        ] {
+            assert_eq!(
+                keybindings.action_for(keycode, keymod),
+                Some(Action::RandomPreset)
+            );
+        }
+        assert_eq!(
+            keybindings.action_for(Keycode::Equals, Mod::LCTRLMOD | Mod::LSHIFTMOD),
+            Some(Action::ToggleLock)
+        );
+    }
+}
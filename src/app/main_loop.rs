@@ -1,124 +1,85 @@
-use crate::app::App;
+use crate::app::keybindings::Action;
+use crate::app::{headless, App};
 use sdl3::event::Event;
-use sdl3::keyboard::Keycode;
-use sdl3::timer::{delay, ticks};
-
-#[cfg(feature = "dummy_audio")]
-use crate::dummy_audio;
 
 impl App {
     pub fn main_loop(&mut self) {
         let config = &self.config;
-        let frame_rate = config.frame_rate.unwrap();
+
+        // write frames to disk when rendering headless
+        if config.headless.unwrap_or(false) {
+            let output_dir = config.output_dir.clone().unwrap_or_else(|| "frames".into());
+            self.frame_writer = Some(
+                headless::FrameWriter::new(&output_dir, config.frames.unwrap_or(300))
+                    .expect("could not set up headless frame output"),
+            );
+
+            // start on the first preset rather than the idle preset
+            self.playlist_play_first();
+        }
 
         // events
         let mut event_pump = self.sdl_context.event_pump().unwrap();
 
         // renderLoop
         'running: loop {
-            // get start time
-            let start_time = ticks();
-
             // check for event
             for event in event_pump.poll_iter() {
                 match event {
-                    // quit (Esc)
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => {
-                        break 'running;
-                    }
-
-                    // Next preset (N, right-arrow)
-                    Event::KeyUp {
-                        keycode: Some(Keycode::N),
-                        ..
-                    } => {
-                        self.playlist_play_next();
-                    }
-                    // XXX: how to collapse these into one case?
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Right),
-                        ..
-                    } => {
-                        self.playlist_play_next();
-                    }
-
-                    // Previous preset (P, left-arrow)
-                    Event::KeyUp {
-                        keycode: Some(Keycode::P),
-                        ..
-                    } => {
-                        self.playlist_play_prev();
-                    }
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Left),
-                        ..
-                    } => {
-                        self.playlist_play_prev();
-                    }
+                    Event::Quit { .. } => break 'running,
 
-                    // Random preset (R)
-                    Event::KeyUp {
-                        keycode: Some(Keycode::R),
+                    // key bindings
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        keymod,
+                        repeat: false,
                         ..
-                    } => {
-                        self.playlist_play_random();
-                    }
-
-                    // Toggle fullscreen (F)
-                    Event::KeyUp {
-                        keycode: Some(Keycode::F),
-                        ..
-                    } => {
-                        self.toggle_fullscreen();
-                    }
-
-                    // Next audio capture input device (ctl-I, cmd-I)
-                    Event::KeyUp {
-                        keycode: Some(Keycode::I),
-                        keymod:
-                            sdl3::keyboard::Mod::LCTRLMOD
-                            | sdl3::keyboard::Mod::RCTRLMOD
-                            | sdl3::keyboard::Mod::LGUIMOD
-                            | sdl3::keyboard::Mod::RGUIMOD,
-                        ..
-                    } => {
-                        self.audio.open_next_device();
-                    }
+                    } => match self.keybindings.action_for(keycode, keymod) {
+                        Some(Action::Quit) => break 'running,
+                        Some(action) => self.run_action(action),
+                        None => {}
+                    },
 
                     // default
                     _ => {}
                 }
             }
 
-            // generate random audio
-            #[cfg(feature = "dummy_audio")]
-            dummy_audio::generate_random_audio_data(&self.pm);
-            
-            // Feed audio data from capture device to projectM
-            #[cfg(not(feature = "dummy_audio"))]
-            self.audio.process_frame_samples();
+            // apply changes to the config file and presets
+            self.process_config_changes();
+            self.process_preset_changes();
+
+            // handle commands from IPC, OSC and MIDI clients
+            #[cfg(unix)]
+            self.process_ipc_requests();
+            self.process_osc_messages();
+            self.process_midi_messages();
+
+            // Feed audio data from the audio source to projectM
+            self.process_frame_samples();
 
             // render a frame
             self.pm.render_frame();
 
+            // report presets switched during the frame
+            self.handle_preset_switches();
+            self.check_preset_duration();
+
+            // draw the on-screen display over the frame
+            self.draw_osd();
+
+            // write the frame to disk when rendering headless
+            if !self.write_headless_frame() {
+                break 'running;
+            }
+
             // swap buffers
             self.window.gl_swap_window();
 
-            if frame_rate > 0 {
-                // calculate frame time
-                let frame_time: i32 = (ticks() - start_time).try_into().unwrap();
-                // what do we need to hit target frame rate?
-                let frame_rate_i32: i32 = frame_rate.try_into().unwrap();
-                let delay_needed: i32 = 1000 / frame_rate_i32 - frame_time;
-                if delay_needed > 0 {
-                    // sleep the remaining frame time
-                    delay(delay_needed.try_into().unwrap());
-                }
-            }
+            // wait for the next frame
+            self.clock.end_frame();
         }
+
+        self.save_stats();
     }
 }
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::app::command::Command;
+use crate::app::App;
+
+/// Actions for notes that aren't mapped in the configuration: the first pads
+/// of most controllers send notes 36 and up.
+const DEFAULT_NOTES: &[(u8, &str)] =
+    &[(36, "next"), (37, "prev"), (38, "random"), (39, "hard_cut")];
+
+/// Settings for controllers that aren't mapped in the configuration.
+const DEFAULT_CONTROLLERS: &[(u8, &str)] = &[
+    (1, "beat_sensitivity"),
+    (20, "preset_duration"),
+    (21, "hard_cut_sensitivity"),
+];
+
+/// A MIDI channel message this frontend reacts to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+}
+
+impl MidiMessage {
+    /// Decode a raw MIDI message. Returns `None` for messages that aren't used,
+    /// including note-ons with velocity 0, which mean note-off.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let status = *bytes.first()?;
+        let channel = status & 0x0f;
+        match (status & 0xf0, bytes.get(1), bytes.get(2)) {
+            (0x90, Some(&note), Some(&velocity)) if velocity > 0 => Some(MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            }),
+            (0xb0, Some(&controller), Some(&value)) => Some(MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            }),
+            (0xc0, Some(&program), _) => Some(MidiMessage::ProgramChange { channel, program }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MidiMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // channels are shown 1-16, like on the devices
+        match self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => write!(
+                f,
+                "note on, channel {}, note {}, velocity {}",
+                channel + 1,
+                note,
+                velocity
+            ),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => write!(
+                f,
+                "control change, channel {}, controller {}, value {}",
+                channel + 1,
+                controller,
+                value
+            ),
+            MidiMessage::ProgramChange { channel, program } => {
+                write!(
+                    f,
+                    "program change, channel {}, program {}",
+                    channel + 1,
+                    program
+                )
+            }
+        }
+    }
+}
+
+/// A setting driven by a controller, scaled from 0-127 onto `min..=max`.
+#[derive(Clone, Debug, PartialEq)]
+struct ControllerMapping {
+    setting: String,
+    min: f64,
+    max: f64,
+}
+
+impl ControllerMapping {
+    /// Parse `<setting> [<min> <max>]`, using the setting's default range if
+    /// none is given.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut words = spec.split_whitespace();
+        let setting = words.next().ok_or("empty controller mapping")?.to_string();
+
+        let (min, max) = match (words.next(), words.next()) {
+            (Some(min), Some(max)) => (
+                min.parse()
+                    .map_err(|_| format!("invalid minimum '{}'", min))?,
+                max.parse()
+                    .map_err(|_| format!("invalid maximum '{}'", max))?,
+            ),
+            (None, None) => match setting.as_str() {
+                "beat_sensitivity" | "hard_cut_sensitivity" => (0.0, 5.0),
+                "preset_duration" => (1.0, 60.0),
+                _ => return Err(format!("no default range for '{}'", setting)),
+            },
+            _ => return Err(format!("'{}' needs both a minimum and a maximum", spec)),
+        };
+
+        Ok(Self { setting, min, max })
+    }
+
+    fn value(&self, value: u8) -> f64 {
+        self.min + (self.max - self.min) * value as f64 / 127.0
+    }
+}
+
+/// Maps notes, controllers and program changes to commands.
+pub struct MidiMap {
+    notes: HashMap<u8, String>,
+    controllers: HashMap<u8, ControllerMapping>,
+}
+
+impl MidiMap {
+    /// Build the map from the configured note and controller tables, which map
+    /// note or controller numbers to actions on top of the defaults. Invalid
+    /// entries are reported and skipped.
+    pub fn new(notes: &HashMap<String, String>, controllers: &HashMap<String, String>) -> Self {
+        let mut map = Self {
+            notes: DEFAULT_NOTES
+                .iter()
+                .map(|(note, action)| (*note, action.to_string()))
+                .collect(),
+            controllers: DEFAULT_CONTROLLERS
+                .iter()
+                .map(|(controller, spec)| (*controller, ControllerMapping::parse(spec).unwrap()))
+                .collect(),
+        };
+
+        for (note, action) in notes {
+            match note.parse() {
+                Ok(note) => {
+                    map.notes.insert(note, action.clone());
+                }
+                Err(_) => println!("Warning: invalid MIDI note number '{}'", note),
+            }
+        }
+        for (controller, spec) in controllers {
+            match (controller.parse(), ControllerMapping::parse(spec)) {
+                (Ok(controller), Ok(mapping)) => {
+                    map.controllers.insert(controller, mapping);
+                }
+                (Err(_), _) => println!("Warning: invalid MIDI controller number '{}'", controller),
+                (_, Err(e)) => println!("Warning: invalid MIDI controller {}: {}", controller, e),
+            }
+        }
+
+        map
+    }
+
+    /// The command triggered by `message`, if any.
+    fn command_for(&self, message: &MidiMessage) -> Option<Command> {
+        match message {
+            MidiMessage::NoteOn { note, .. } => match self.notes.get(note)?.as_str() {
+                "next" => Some(Command::Next),
+                "prev" => Some(Command::Prev),
+                "random" => Some(Command::Random),
+                "hard_cut" => Some(Command::HardCut),
+                "lock" => Some(Command::Lock { locked: None }),
+                "fullscreen" => Some(Command::Fullscreen),
+                action => {
+                    println!("Unknown MIDI note action '{}'", action);
+                    None
+                }
+            },
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => {
+                let mapping = self.controllers.get(controller)?;
+                Some(Command::Set {
+                    key: mapping.setting.clone(),
+                    value: json!(mapping.value(*value)),
+                })
+            }
+            MidiMessage::ProgramChange { program, .. } => Some(Command::Load {
+                preset: None,
+                index: Some(*program as u32),
+            }),
+        }
+    }
+}
+
+/// Receives MIDI messages from an input port on a background thread.
+#[cfg(feature = "midi")]
+pub struct MidiInput {
+    receiver: std::sync::mpsc::Receiver<Vec<u8>>,
+    _connection: midir::MidiInputConnection<()>,
+}
+
+#[cfg(feature = "midi")]
+impl MidiInput {
+    /// Connect to the input port matching `port` (a name, name pattern or
+    /// index), or create a virtual port other programs can connect to if
+    /// `port` is `virtual`.
+    pub fn open(port: &str) -> Result<Self, String> {
+        let input = midir::MidiInput::new("projectM").map_err(|e| e.to_string())?;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let callback = move |_timestamp: u64, message: &[u8], _: &mut ()| {
+            let _ = sender.send(message.to_vec());
+        };
+
+        let connection = if port.eq_ignore_ascii_case("virtual") {
+            #[cfg(unix)]
+            {
+                use midir::os::unix::VirtualInput;
+                println!("Created virtual MIDI input port 'projectM'");
+                input
+                    .create_virtual("projectM", callback, ())
+                    .map_err(|e| e.to_string())?
+            }
+            #[cfg(not(unix))]
+            return Err("virtual MIDI ports are not supported on this platform".to_string());
+        } else {
+            let ports = input.ports();
+            let names: Vec<String> = ports
+                .iter()
+                .map(|p| input.port_name(p).unwrap_or_default())
+                .collect();
+
+            println!("MIDI Inputs:");
+            for (index, name) in names.iter().enumerate() {
+                println!(" {}: {}", index, name);
+            }
+
+            let index = crate::app::audio::capture::match_device(&names, port)
+                .ok_or_else(|| format!("MIDI input '{}' not found", port))?;
+            println!("Using MIDI input '{}'", names[index]);
+            input
+                .connect(&ports[index], "projectM", callback, ())
+                .map_err(|e| e.to_string())?
+        };
+
+        Ok(Self {
+            receiver,
+            _connection: connection,
+        })
+    }
+
+    /// Raw messages received since the last call.
+    fn poll(&mut self) -> Vec<Vec<u8>> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// Placeholder for builds without the `midi` feature; can't be opened.
+#[cfg(not(feature = "midi"))]
+pub enum MidiInput {}
+
+#[cfg(not(feature = "midi"))]
+impl MidiInput {
+    pub fn open(_port: &str) -> Result<Self, String> {
+        Err("built without MIDI support, rebuild with `--features midi`".to_string())
+    }
+
+    fn poll(&mut self) -> Vec<Vec<u8>> {
+        match *self {}
+    }
+}
+
+impl App {
+    /// Run the commands mapped to MIDI messages received since the last frame,
+    /// printing each message in MIDI learn mode. Call once per frame.
+    pub fn process_midi_messages(&mut self) {
+        let (input, map) = match (self.midi_input.as_mut(), self.midi_map.as_ref()) {
+            (Some(input), Some(map)) => (input, map),
+            _ => return,
+        };
+        let learn = self.config.midi_learn.unwrap_or(false);
+
+        let mut commands = Vec::new();
+        for message in input.poll().iter().filter_map(|m| MidiMessage::parse(m)) {
+            if learn {
+                println!("MIDI: {}", message);
+            }
+            commands.extend(map.command_for(&message));
+        }
+
+        for command in commands {
+            match self.run_command(command) {
+                Ok(Value::Null) => {}
+                Ok(result) => println!("MIDI: {}", result),
+                Err(e) => println!("MIDI command failed: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(
+            MidiMessage::parse(&[0x91, 36, 100]),
+            Some(MidiMessage::NoteOn {
+                channel: 1,
+                note: 36,
+                velocity: 100
+            })
+        );
+        // note on with velocity 0 is a note off
+        assert_eq!(MidiMessage::parse(&[0x90, 36, 0]), None);
+        assert_eq!(MidiMessage::parse(&[0x80, 36, 64]), None);
+        assert_eq!(
+            MidiMessage::parse(&[0xb0, 7, 127]),
+            Some(MidiMessage::ControlChange {
+                channel: 0,
+                controller: 7,
+                value: 127
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xcf, 5]),
+            Some(MidiMessage::ProgramChange {
+                channel: 15,
+                program: 5
+            })
+        );
+        assert_eq!(MidiMessage::parse(&[0xb0, 7]), None);
+        assert_eq!(MidiMessage::parse(&[]), None);
+    }
+
+    #[test]
+    fn test_midi_map() {
+        let notes = HashMap::from([("60".to_string(), "random".to_string())]);
+        let controllers = HashMap::from([("7".to_string(), "preset_duration 10 137".to_string())]);
+        let map = MidiMap::new(&notes, &controllers);
+
+        let note = |note| MidiMessage::NoteOn {
+            channel: 0,
+            note,
+            velocity: 100,
+        };
+        assert_eq!(map.command_for(&note(36)), Some(Command::Next));
+        assert_eq!(map.command_for(&note(60)), Some(Command::Random));
+        assert_eq!(map.command_for(&note(61)), None);
+
+        let controller = |controller, value| MidiMessage::ControlChange {
+            channel: 0,
+            controller,
+            value,
+        };
+        assert_eq!(
+            map.command_for(&controller(7, 127)),
+            Some(Command::Set {
+                key: "preset_duration".to_string(),
+                value: json!(137.0)
+            })
+        );
+        assert_eq!(
+            map.command_for(&controller(1, 0)),
+            Some(Command::Set {
+                key: "beat_sensitivity".to_string(),
+                value: json!(0.0)
+            })
+        );
+        assert_eq!(map.command_for(&controller(2, 64)), None);
+
+        assert_eq!(
+            map.command_for(&MidiMessage::ProgramChange {
+                channel: 0,
+                program: 12
+            }),
+            Some(Command::Load {
+                preset: None,
+                index: Some(12)
+            })
+        );
+    }
+}
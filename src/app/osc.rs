@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, UdpSocket};
+
+use serde_json::{json, Value};
+
+use crate::app::command::Command;
+use crate::app::App;
+
+/// Actions for OSC addresses that aren't mapped in the configuration.
+///
+/// An action is a command name, followed by the setting name for `set`. The
+/// message's first argument supplies the index, value or lock state.
+const DEFAULT_ADDRESSES: &[(&str, &str)] = &[
+    ("/projectm/preset/next", "next"),
+    ("/projectm/preset/prev", "prev"),
+    ("/projectm/preset/random", "random"),
+    ("/projectm/preset/index", "index"),
+    ("/projectm/preset/lock", "lock"),
+    ("/projectm/hardcut", "hard_cut"),
+    ("/projectm/fullscreen", "fullscreen"),
+    ("/projectm/beat_sensitivity", "set beat_sensitivity"),
+    ("/projectm/preset_duration", "set preset_duration"),
+];
+
+/// An OSC message argument.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArg::Int(value) => Some(*value as f64),
+            OscArg::Float(value) => Some(*value),
+            OscArg::Bool(value) => Some(*value as u8 as f64),
+            OscArg::String(value) => value.parse().ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Reads OSC-encoded data, which is aligned to 4 bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if count > self.data.len() {
+            return Err("truncated packet".to_string());
+        }
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("unterminated string")?;
+        let string = String::from_utf8_lossy(&self.data[..len]).into_owned();
+        // the terminating NUL plus padding to a multiple of 4
+        self.take((len + 4) & !3)?;
+        Ok(string)
+    }
+
+    fn blob(&mut self) -> Result<&'a [u8], String> {
+        let len = i32::from_be_bytes(self.bytes()?).max(0) as usize;
+        let blob = self.take(len)?;
+        self.take((4 - len % 4) % 4)?;
+        Ok(blob)
+    }
+}
+
+/// Decode an OSC packet, flattening bundles into their messages.
+pub fn parse_packet(data: &[u8]) -> Result<Vec<OscMessage>, String> {
+    let mut reader = Reader { data };
+
+    if data.starts_with(b"#bundle\0") {
+        reader.take(16)?; // "#bundle" and the time tag
+        let mut messages = Vec::new();
+        while !reader.data.is_empty() {
+            messages.extend(parse_packet(reader.blob()?)?);
+        }
+        return Ok(messages);
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("invalid OSC address: {}", address));
+    }
+
+    // messages from old implementations may lack the type tag string
+    let type_tags = if reader.data.is_empty() {
+        String::new()
+    } else {
+        reader.string()?
+    };
+
+    let mut args = Vec::new();
+    for tag in type_tags.chars().skip_while(|&c| c == ',') {
+        let arg = match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(reader.bytes()?) as i64),
+            'h' => OscArg::Int(i64::from_be_bytes(reader.bytes()?)),
+            'f' => OscArg::Float(f32::from_be_bytes(reader.bytes()?) as f64),
+            'd' => OscArg::Float(f64::from_be_bytes(reader.bytes()?)),
+            's' | 'S' => OscArg::String(reader.string()?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            'b' => {
+                reader.blob()?;
+                continue;
+            }
+            // arguments without data: nil, infinitum, array brackets
+            'N' | 'I' | '[' | ']' => continue,
+            _ => return Err(format!("unsupported OSC type tag '{}'", tag)),
+        };
+        args.push(arg);
+    }
+
+    Ok(vec![OscMessage { address, args }])
+}
+
+/// Translate an action from the address map into a command.
+///
+/// Returns `None` for messages that shouldn't trigger anything, like the
+/// release of a button which sends 0.
+fn command_for(action: &str, args: &[OscArg]) -> Result<Option<Command>, String> {
+    let first = args.first().and_then(OscArg::as_f64);
+    let pressed = first != Some(0.0);
+
+    let mut words = action.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("next"), None) => Command::Next,
+        (Some("prev"), None) => Command::Prev,
+        (Some("random"), None) => Command::Random,
+        (Some("hard_cut"), None) => Command::HardCut,
+        (Some("fullscreen"), None) => Command::Fullscreen,
+        (Some("lock"), None) => {
+            return Ok(Some(Command::Lock {
+                locked: first.map(|value| value != 0.0),
+            }))
+        }
+        (Some("index"), None) => {
+            let index = first.ok_or("index needs an argument")?;
+            return Ok(Some(Command::Load {
+                preset: None,
+                index: Some(index.max(0.0) as u32),
+            }));
+        }
+        (Some("set"), Some(key)) => {
+            let value = first.ok_or_else(|| format!("{} needs a number", key))?;
+            return Ok(Some(Command::Set {
+                key: key.to_string(),
+                value: json!(value),
+            }));
+        }
+        _ => return Err(format!("unknown OSC action '{}'", action)),
+    };
+
+    Ok(pressed.then_some(command))
+}
+
+/// Receives OSC messages over UDP and maps their addresses to actions.
+pub struct OscListener {
+    socket: UdpSocket,
+    addresses: HashMap<String, String>,
+}
+
+impl OscListener {
+    /// Listen on `port` at `address`. `addresses` maps OSC addresses to
+    /// actions, on top of the default address map.
+    pub fn bind(
+        address: IpAddr,
+        port: u16,
+        addresses: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        let socket = UdpSocket::bind((address, port))
+            .map_err(|e| format!("could not listen on UDP {}:{}: {}", address, port, e))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let mut address_map: HashMap<String, String> = DEFAULT_ADDRESSES
+            .iter()
+            .map(|(address, action)| (address.to_string(), action.to_string()))
+            .collect();
+        address_map.extend(addresses.clone());
+
+        println!(
+            "Listening for OSC messages on {}",
+            socket.local_addr().map_err(|e| e.to_string())?
+        );
+
+        Ok(Self {
+            socket,
+            addresses: address_map,
+        })
+    }
+
+    /// Messages received since the last call.
+    fn poll(&mut self) -> Vec<OscMessage> {
+        let mut messages = Vec::new();
+        let mut buf = [0u8; 65536];
+
+        while let Ok(len) = self.socket.recv(&mut buf) {
+            match parse_packet(&buf[..len]) {
+                Ok(packet) => messages.extend(packet),
+                Err(e) => println!("Invalid OSC packet: {}", e),
+            }
+        }
+
+        messages
+    }
+}
+
+impl App {
+    /// Run the actions mapped to OSC messages received since the last frame.
+    /// Call once per frame.
+    pub fn process_osc_messages(&mut self) {
+        let listener = match self.osc_listener.as_mut() {
+            Some(listener) => listener,
+            None => return,
+        };
+
+        let mut commands = Vec::new();
+        for message in listener.poll() {
+            let action = match listener.addresses.get(&message.address) {
+                Some(action) => action,
+                None => {
+                    println!("Unmapped OSC address: {}", message.address);
+                    continue;
+                }
+            };
+            match command_for(action, &message.args) {
+                Ok(Some(command)) => commands.push(command),
+                Ok(None) => {}
+                Err(e) => println!("OSC {}: {}", message.address, e),
+            }
+        }
+
+        for command in commands {
+            match self.run_command(command) {
+                Ok(Value::Null) => {}
+                Ok(result) => println!("OSC: {}", result),
+                Err(e) => println!("OSC command failed: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    /// Encode a message with float arguments, like TouchOSC sends.
+    fn encode(address: &str, args: &[f32]) -> Vec<u8> {
+        fn pad_string(packet: &mut Vec<u8>, string: &str) {
+            packet.extend_from_slice(string.as_bytes());
+            packet.extend(std::iter::repeat(0).take(4 - string.len() % 4));
+        }
+
+        let mut packet = Vec::new();
+        pad_string(&mut packet, address);
+        pad_string(&mut packet, &format!(",{}", "f".repeat(args.len())));
+        for arg in args {
+            packet.extend_from_slice(&arg.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let message = |address: &str, args| OscMessage {
+            address: address.to_string(),
+            args,
+        };
+
+        assert_eq!(
+            parse_packet(&encode("/projectm/beat_sensitivity", &[1.5])),
+            Ok(vec![message(
+                "/projectm/beat_sensitivity",
+                vec![OscArg::Float(1.5)]
+            )])
+        );
+
+        // a bundle holding an int message and a message without arguments
+        let mut int_message = b"/projectm/preset/index\0\0,i\0\0".to_vec();
+        int_message.extend_from_slice(&7i32.to_be_bytes());
+        let next_message = encode("/projectm/preset/next", &[]);
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in [&int_message, &next_message] {
+            bundle.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(element);
+        }
+        assert_eq!(
+            parse_packet(&bundle),
+            Ok(vec![
+                message("/projectm/preset/index", vec![OscArg::Int(7)]),
+                message("/projectm/preset/next", vec![]),
+            ])
+        );
+
+        assert!(parse_packet(b"/projectm\0\0\0,f\0\0\0").is_err());
+        assert!(parse_packet(b"no address\0\0").is_err());
+    }
+
+    #[test]
+    fn test_command_for() {
+        assert_eq!(command_for("next", &[]), Ok(Some(Command::Next)));
+        // button release
+        assert_eq!(command_for("next", &[OscArg::Float(0.0)]), Ok(None));
+        assert_eq!(
+            command_for("index", &[OscArg::Int(3)]),
+            Ok(Some(Command::Load {
+                preset: None,
+                index: Some(3)
+            }))
+        );
+        assert_eq!(
+            command_for("set beat_sensitivity", &[OscArg::Float(1.25)]),
+            Ok(Some(Command::Set {
+                key: "beat_sensitivity".to_string(),
+                value: json!(1.25)
+            }))
+        );
+        assert!(command_for("set beat_sensitivity", &[]).is_err());
+        assert!(command_for("explode", &[]).is_err());
+    }
+
+    #[test]
+    fn test_receive_on_loopback() {
+        let mut listener =
+            OscListener::bind(IpAddr::V4(Ipv4Addr::LOCALHOST), 0, &HashMap::new()).unwrap();
+        let port = listener.socket.local_addr().unwrap().port();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(
+                &encode("/projectm/preset/next", &[1.0]),
+                ("127.0.0.1", port),
+            )
+            .unwrap();
+
+        // the packet is delivered asynchronously
+        let mut messages = Vec::new();
+        for _ in 0..100 {
+            messages = listener.poll();
+            if !messages.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].address, "/projectm/preset/next");
+        assert_eq!(listener.addresses[&messages[0].address], "next");
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::app::App;
+
+mod font;
+mod text;
+
+use text::{TextBatch, TextRenderer, GLYPH_SIZE};
+
+/// Messages on screen at once; older ones are dropped.
+const MAX_MESSAGES: usize = 5;
+/// Time at the end of a message's duration over which it fades out.
+const FADE_TIME: Duration = Duration::from_millis(500);
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// What the on-screen display shows, cycled through with the toggle key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OsdMode {
+    Off,
+    /// Only transient messages, such as preset switches.
+    Messages,
+    /// Messages plus the status panel with the preset, audio device and FPS.
+    Status,
+}
+
+impl OsdMode {
+    fn next(self) -> Self {
+        match self {
+            OsdMode::Off => OsdMode::Messages,
+            OsdMode::Messages => OsdMode::Status,
+            OsdMode::Status => OsdMode::Off,
+        }
+    }
+}
+
+struct Message {
+    text: String,
+    shown_at: Duration,
+}
+
+/// Frames per second, averaged over about a second.
+struct FpsCounter {
+    window_start: Option<Duration>,
+    frames: u32,
+    fps: Option<f64>,
+}
+
+impl FpsCounter {
+    fn tick(&mut self, now: Duration) {
+        let window_start = *self.window_start.get_or_insert(now);
+        let elapsed = now.saturating_sub(window_start);
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = Some(self.frames as f64 / elapsed.as_secs_f64());
+            self.window_start = Some(now);
+            self.frames = 0;
+        }
+        self.frames += 1;
+    }
+}
+
+/// Text overlay drawn on top of the visualization.
+pub struct Osd {
+    renderer: TextRenderer,
+    mode: OsdMode,
+    messages: VecDeque<Message>,
+    message_duration: Duration,
+    fps: FpsCounter,
+}
+
+impl Osd {
+    /// Set up rendering for the overlay. Needs a current GL context.
+    pub fn new(mode: OsdMode, message_duration: Duration) -> Result<Self, String> {
+        Ok(Self {
+            renderer: TextRenderer::new()?,
+            mode,
+            messages: VecDeque::new(),
+            message_duration,
+            fps: FpsCounter {
+                window_start: None,
+                frames: 0,
+                fps: None,
+            },
+        })
+    }
+
+    pub fn set_message_duration(&mut self, message_duration: Duration) {
+        self.message_duration = message_duration;
+    }
+
+    /// Show `text` until the message duration has passed since `now`.
+    pub fn message(&mut self, text: String, now: Duration) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(Message {
+            text,
+            shown_at: now,
+        });
+    }
+
+    /// Draw the overlay for the frame at `now` over a `width` by `height`
+    /// framebuffer. `status` is only called when the status panel is shown.
+    fn draw(
+        &mut self,
+        now: Duration,
+        width: u32,
+        height: u32,
+        status: impl FnOnce(f64) -> Vec<String>,
+    ) {
+        self.fps.tick(now);
+
+        let duration = self.message_duration;
+        self.messages
+            .retain(|message| now.saturating_sub(message.shown_at) < duration);
+        if self.mode == OsdMode::Off {
+            return;
+        }
+
+        // scale the 8 pixel font with the window, to stay readable from a distance
+        let scale = (height as f32 / 360.0).floor().max(1.0);
+        let padding = 2.0 * scale;
+        let margin = 4.0 * padding;
+        let line_height = GLYPH_SIZE * scale + 2.0 * padding;
+        let max_chars =
+            ((width as f32 - 2.0 * (margin + padding)) / (GLYPH_SIZE * scale)).max(0.0) as usize;
+
+        let mut batch = TextBatch::default();
+        let mut line = |y: f32, text: &str, alpha: f32| {
+            let text = truncate(text, max_chars);
+            let fade = |color: [f32; 4]| [color[0], color[1], color[2], color[3] * alpha];
+            batch.rect(
+                margin,
+                y,
+                TextBatch::text_width(&text, scale) + 2.0 * padding,
+                line_height,
+                fade(BACKGROUND_COLOR),
+            );
+            batch.text(
+                margin + padding,
+                y + padding,
+                scale,
+                &text,
+                fade(TEXT_COLOR),
+            );
+        };
+
+        if self.mode == OsdMode::Status {
+            let fps = self.fps.fps.unwrap_or(0.0);
+            for (i, text) in status(fps).iter().enumerate() {
+                line(margin + i as f32 * line_height, text, 1.0);
+            }
+        }
+
+        // newest message at the bottom
+        let bottom = height as f32 - margin;
+        for (i, message) in self.messages.iter().rev().enumerate() {
+            let age = now.saturating_sub(message.shown_at);
+            let y = bottom - (i + 1) as f32 * line_height;
+            line(y, &message.text, message_alpha(age, duration));
+        }
+
+        self.renderer.draw(&batch, width, height);
+    }
+}
+
+/// Opacity of a message shown `age` ago, fading out at the end of `duration`.
+fn message_alpha(age: Duration, duration: Duration) -> f32 {
+    let remaining = duration.saturating_sub(age);
+    if remaining >= FADE_TIME {
+        1.0
+    } else {
+        remaining.as_secs_f32() / FADE_TIME.as_secs_f32()
+    }
+}
+
+/// Shorten `text` to at most `max_chars` characters, ending it with `...` if
+/// anything was cut off and there's room for it.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    if max_chars <= 3 {
+        return text.chars().take(max_chars).collect();
+    }
+    let kept: String = text.chars().take(max_chars - 3).collect();
+    format!("{}...", kept)
+}
+
+impl App {
+    /// Show a transient message on the on-screen display, if it's enabled.
+    pub fn osd_message(&mut self, text: String) {
+        let now = self.clock.now();
+        if let Some(osd) = self.osd.as_mut() {
+            osd.message(text, now);
+        }
+    }
+
+    /// Cycle between showing nothing, only messages, and messages plus status.
+    pub fn toggle_osd(&mut self) {
+        if let Some(osd) = self.osd.as_mut() {
+            osd.mode = osd.mode.next();
+            println!("OSD: {:?}", osd.mode);
+        }
+    }
+
+    /// Draw the on-screen display over the rendered frame. Call once per
+    /// frame, before swapping buffers.
+    pub fn draw_osd(&mut self) {
+        let osd = match self.osd.as_mut() {
+            Some(osd) => osd,
+            None => return,
+        };
+
+        let (width, height) = self.window.size_in_pixels();
+        let playlist = &self.playlist;
+        let pm = &self.pm;
+        let audio_source = &self.audio_source;
+        osd.draw(self.clock.now(), width, height, |fps| {
+            let position = playlist.position();
+            let preset = playlist
+                .item(position)
+                .and_then(|path| {
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                })
+                .unwrap_or_else(|| "none".to_string());
+            let locked = if pm.get_preset_locked() {
+                " (locked)"
+            } else {
+                ""
+            };
+            vec![
+                format!("Preset: {}{}", preset, locked),
+                format!("Playlist: {}/{}", position + 1, playlist.len()),
+                format!("Audio: {}", audio_source.describe()),
+                format!("FPS: {:.1}", fps),
+            ]
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_alpha() {
+        let duration = Duration::from_secs(3);
+        assert_eq!(message_alpha(Duration::ZERO, duration), 1.0);
+        assert_eq!(message_alpha(Duration::from_millis(2500), duration), 1.0);
+        assert_eq!(message_alpha(Duration::from_millis(2750), duration), 0.5);
+        assert_eq!(message_alpha(Duration::from_secs(4), duration), 0.0);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("preset.milk", 20), "preset.milk");
+        assert_eq!(truncate("a very long preset name.milk", 10), "a very ...");
+        assert_eq!(truncate("preset", 2), "pr");
+    }
+
+    #[test]
+    fn test_fps_counter() {
+        let mut counter = FpsCounter {
+            window_start: None,
+            frames: 0,
+            fps: None,
+        };
+        for frame in 0..=60 {
+            counter.tick(Duration::from_millis(frame * 20));
+        }
+        assert_eq!(counter.fps, Some(50.0));
+    }
+}
@@ -0,0 +1,394 @@
+use std::ffi::CString;
+
+use gl::types::{GLenum, GLint, GLuint};
+
+use super::font::{FIRST_CHAR, GLYPHS};
+
+/// Size of a glyph in font pixels.
+pub const GLYPH_SIZE: f32 = 8.0;
+
+/// Floats per vertex: position, texture coordinates and color.
+const VERTEX_SIZE: usize = 8;
+
+/// Index of the solid block glyph in the font texture.
+const SOLID_GLYPH: usize = GLYPHS.len() - 1;
+
+const VERTEX_SHADER: &str = r#"#version 330 core
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec4 vertex_color;
+uniform vec2 viewport;
+out vec2 uv;
+out vec4 color;
+void main() {
+    uv = tex_coord;
+    color = vertex_color;
+    // pixel coordinates with the origin at the top left
+    gl_Position = vec4(position / viewport * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"#version 330 core
+in vec2 uv;
+in vec4 color;
+uniform sampler2D font;
+out vec4 frag_color;
+void main() {
+    frag_color = vec4(color.rgb, color.a * texture(font, uv).r);
+}
+"#;
+
+/// Quads of text and backgrounds to draw in one go, in pixel coordinates
+/// with the origin at the top left.
+#[derive(Default)]
+pub struct TextBatch {
+    vertices: Vec<f32>,
+}
+
+impl TextBatch {
+    /// Width in pixels of `text` drawn at `scale`.
+    pub fn text_width(text: &str, scale: f32) -> f32 {
+        text.chars().count() as f32 * GLYPH_SIZE * scale
+    }
+
+    /// Add a filled rectangle.
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        // sample the middle of the solid glyph to stay clear of its neighbors
+        let u = (SOLID_GLYPH as f32 + 0.5) / GLYPHS.len() as f32;
+        self.quad([x, y, x + width, y + height], [u, 0.5, u, 0.5], color);
+    }
+
+    /// Add a line of text with its top left corner at `x`, `y`, with each font
+    /// pixel `scale` pixels wide. Characters the font lacks are drawn as `?`.
+    pub fn text(&mut self, x: f32, y: f32, scale: f32, text: &str, color: [f32; 4]) {
+        let size = GLYPH_SIZE * scale;
+        for (i, c) in text.chars().enumerate() {
+            let glyph = match c {
+                ' '..='~' => c as usize - FIRST_CHAR as usize,
+                _ => (b'?' - FIRST_CHAR) as usize,
+            };
+            let u0 = glyph as f32 / GLYPHS.len() as f32;
+            let u1 = (glyph + 1) as f32 / GLYPHS.len() as f32;
+            let left = x + i as f32 * size;
+            self.quad([left, y, left + size, y + size], [u0, 0.0, u1, 1.0], color);
+        }
+    }
+
+    /// Add two triangles covering `rect` (left, top, right, bottom), mapped to
+    /// the texture coordinates in `uv`.
+    fn quad(&mut self, rect: [f32; 4], uv: [f32; 4], color: [f32; 4]) {
+        let [left, top, right, bottom] = rect;
+        let [u0, v0, u1, v1] = uv;
+        let corners = [
+            (left, top, u0, v0),
+            (right, top, u1, v0),
+            (left, bottom, u0, v1),
+            (right, top, u1, v0),
+            (right, bottom, u1, v1),
+            (left, bottom, u0, v1),
+        ];
+        for (x, y, u, v) in corners {
+            self.vertices.extend_from_slice(&[x, y, u, v]);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    fn vertex_count(&self) -> usize {
+        self.vertices.len() / VERTEX_SIZE
+    }
+}
+
+/// Draws [`TextBatch`]es with OpenGL, using the embedded bitmap font.
+pub struct TextRenderer {
+    program: GLuint,
+    vao: GLuint,
+    vbo: GLuint,
+    texture: GLuint,
+    viewport_location: GLint,
+}
+
+impl TextRenderer {
+    /// Create the shaders, buffers and font texture. Needs a current GL context.
+    pub fn new() -> Result<Self, String> {
+        unsafe {
+            let program = link_program(VERTEX_SHADER, FRAGMENT_SHADER)?;
+            let viewport_location = uniform_location(program, "viewport");
+            gl::UseProgram(program);
+            gl::Uniform1i(uniform_location(program, "font"), 0);
+            gl::UseProgram(0);
+
+            let mut vao = 0;
+            let mut vbo = 0;
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let stride = (VERTEX_SIZE * std::mem::size_of::<f32>()) as i32;
+            for (index, size, offset) in [(0, 2, 0), (1, 2, 2), (2, 4, 4)] {
+                gl::EnableVertexAttribArray(index);
+                gl::VertexAttribPointer(
+                    index,
+                    size,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    stride,
+                    (offset * std::mem::size_of::<f32>()) as *const _,
+                );
+            }
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            // all glyphs side by side in a single row, one byte per pixel
+            let width = GLYPH_SIZE as usize * GLYPHS.len();
+            let height = GLYPH_SIZE as usize;
+            let mut pixels = vec![0u8; width * height];
+            for (glyph, rows) in GLYPHS.iter().enumerate() {
+                for (y, row) in rows.iter().enumerate() {
+                    for x in 0..8 {
+                        if row & (1 << x) != 0 {
+                            pixels[y * width + glyph * 8 + x] = 255;
+                        }
+                    }
+                }
+            }
+
+            let mut texture = 0;
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R8 as i32,
+                width as i32,
+                height as i32,
+                0,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr().cast(),
+            );
+            for (parameter, value) in [
+                (gl::TEXTURE_MIN_FILTER, gl::NEAREST),
+                (gl::TEXTURE_MAG_FILTER, gl::NEAREST),
+                (gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE),
+                (gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE),
+            ] {
+                gl::TexParameteri(gl::TEXTURE_2D, parameter, value as i32);
+            }
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            Ok(Self {
+                program,
+                vao,
+                vbo,
+                texture,
+                viewport_location,
+            })
+        }
+    }
+
+    /// Draw `batch` over the default framebuffer, which is `width` by `height`
+    /// pixels. Restores the GL state projectM relies on afterwards.
+    pub fn draw(&self, batch: &TextBatch, width: u32, height: u32) {
+        if batch.is_empty() {
+            return;
+        }
+
+        unsafe {
+            let saved = SavedState::save();
+
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+            gl::UseProgram(self.program);
+            gl::Uniform2f(self.viewport_location, width as f32, height as f32);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(batch.vertices.as_slice()) as isize,
+                batch.vertices.as_ptr().cast(),
+                gl::STREAM_DRAW,
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, batch.vertex_count() as i32);
+
+            saved.restore();
+        }
+    }
+}
+
+impl Drop for TextRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+/// GL state changed by [`TextRenderer::draw`].
+struct SavedState {
+    viewport: [GLint; 4],
+    blend: bool,
+    depth_test: bool,
+    blend_func: [GLint; 4],
+    program: GLint,
+    vao: GLint,
+    array_buffer: GLint,
+    active_texture: GLint,
+    texture: GLint,
+    framebuffer: GLint,
+}
+
+impl SavedState {
+    unsafe fn save() -> Self {
+        let integer = |name: GLenum| {
+            let mut value = 0;
+            gl::GetIntegerv(name, &mut value);
+            value
+        };
+
+        let mut viewport = [0; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        let active_texture = integer(gl::ACTIVE_TEXTURE);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        Self {
+            viewport,
+            blend: gl::IsEnabled(gl::BLEND) == gl::TRUE,
+            depth_test: gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE,
+            blend_func: [
+                integer(gl::BLEND_SRC_RGB),
+                integer(gl::BLEND_DST_RGB),
+                integer(gl::BLEND_SRC_ALPHA),
+                integer(gl::BLEND_DST_ALPHA),
+            ],
+            program: integer(gl::CURRENT_PROGRAM),
+            vao: integer(gl::VERTEX_ARRAY_BINDING),
+            array_buffer: integer(gl::ARRAY_BUFFER_BINDING),
+            active_texture,
+            texture: integer(gl::TEXTURE_BINDING_2D),
+            framebuffer: integer(gl::DRAW_FRAMEBUFFER_BINDING),
+        }
+    }
+
+    unsafe fn restore(&self) {
+        let enable = |capability, enabled| {
+            if enabled {
+                gl::Enable(capability);
+            } else {
+                gl::Disable(capability);
+            }
+        };
+
+        let [x, y, width, height] = self.viewport;
+        gl::Viewport(x, y, width, height);
+        enable(gl::BLEND, self.blend);
+        enable(gl::DEPTH_TEST, self.depth_test);
+        let [src_rgb, dst_rgb, src_alpha, dst_alpha] = self.blend_func.map(|f| f as GLenum);
+        gl::BlendFuncSeparate(src_rgb, dst_rgb, src_alpha, dst_alpha);
+        gl::UseProgram(self.program as GLuint);
+        gl::BindVertexArray(self.vao as GLuint);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.array_buffer as GLuint);
+        gl::BindTexture(gl::TEXTURE_2D, self.texture as GLuint);
+        gl::ActiveTexture(self.active_texture as GLenum);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer as GLuint);
+    }
+}
+
+unsafe fn uniform_location(program: GLuint, name: &str) -> GLint {
+    let name = CString::new(name).unwrap();
+    gl::GetUniformLocation(program, name.as_ptr())
+}
+
+unsafe fn compile_shader(kind: GLenum, source: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    let source = CString::new(source).unwrap();
+    gl::ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+    gl::CompileShader(shader);
+
+    let mut status = 0;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+    if status == gl::TRUE as GLint {
+        return Ok(shader);
+    }
+
+    let mut log = vec![0u8; 1024];
+    let mut len = 0;
+    gl::GetShaderInfoLog(shader, log.len() as i32, &mut len, log.as_mut_ptr().cast());
+    gl::DeleteShader(shader);
+    log.truncate(len.max(0) as usize);
+    Err(format!(
+        "could not compile OSD shader: {}",
+        String::from_utf8_lossy(&log)
+    ))
+}
+
+unsafe fn link_program(vertex_source: &str, fragment_source: &str) -> Result<GLuint, String> {
+    let vertex_shader = compile_shader(gl::VERTEX_SHADER, vertex_source)?;
+    let fragment_shader = match compile_shader(gl::FRAGMENT_SHADER, fragment_source) {
+        Ok(shader) => shader,
+        Err(e) => {
+            gl::DeleteShader(vertex_shader);
+            return Err(e);
+        }
+    };
+
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vertex_shader);
+    gl::AttachShader(program, fragment_shader);
+    gl::LinkProgram(program);
+    gl::DeleteShader(vertex_shader);
+    gl::DeleteShader(fragment_shader);
+
+    let mut status = 0;
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+    if status != gl::TRUE as GLint {
+        gl::DeleteProgram(program);
+        return Err("could not link OSD shader program".to_string());
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_batch() {
+        let mut batch = TextBatch::default();
+        assert!(batch.is_empty());
+
+        let white = [1.0; 4];
+        batch.text(10.0, 20.0, 2.0, "Hi", white);
+        assert_eq!(batch.vertex_count(), 12);
+
+        // the second glyph starts one glyph width (8 font pixels at scale 2) further
+        let second = &batch.vertices[6 * VERTEX_SIZE..];
+        assert_eq!(&second[..2], &[26.0, 20.0]);
+        // and ends 16 pixels lower
+        assert_eq!(second[4 * VERTEX_SIZE + 1], 36.0);
+
+        // 'i' is glyph 73 of 96
+        let i = (b'i' - FIRST_CHAR) as f32;
+        assert_eq!(second[2], i / 96.0);
+
+        // characters the font lacks are drawn as '?'
+        let mut batch = TextBatch::default();
+        batch.text(0.0, 0.0, 1.0, "é", white);
+        assert_eq!(batch.vertices[2], (b'?' - FIRST_CHAR) as f32 / 96.0);
+
+        assert_eq!(TextBatch::text_width("Hello", 3.0), 120.0);
+    }
+}
@@ -1,22 +1,690 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::distr::weighted::WeightedIndex;
+use rand::distr::Distribution;
+use rand::seq::SliceRandom;
+use rand::RngExt;
 
 use crate::app::App;
 
+mod file;
+mod handle;
+mod preset_list;
+mod watch;
+
+pub use file::PresetOptions;
+pub use handle::Playlist;
+pub use preset_list::PresetList;
+pub use watch::PresetWatcher;
+use watch::{preset_path_contains, preset_path_files};
+
+/// libprojectM's preset duration when none is configured.
+const DEFAULT_PRESET_DURATION: f64 = 30.0;
+/// Preset duration handed to libprojectM while the app times presets itself,
+/// long enough that libprojectM's own timer never runs out.
+const APP_TIMED_PRESET_DURATION: f64 = 1e9;
+
+/// Order of the presets in the playlist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistOrder {
+    /// By file name, ignoring directories.
+    Name,
+    /// By full path.
+    Path,
+    /// By modification time, oldest first.
+    Modified,
+    /// Shuffled once with the app's (possibly seeded) RNG.
+    Random,
+}
+
+impl PlaylistOrder {
+    pub fn parse(order: &str) -> Result<Self, String> {
+        match order.to_ascii_lowercase().as_str() {
+            "name" => Ok(PlaylistOrder::Name),
+            "path" => Ok(PlaylistOrder::Path),
+            "mtime" | "modified" => Ok(PlaylistOrder::Modified),
+            "random" => Ok(PlaylistOrder::Random),
+            _ => Err(format!(
+                "unknown playlist order '{}', expected name, path, mtime or random",
+                order
+            )),
+        }
+    }
+}
+
 impl App {
-    /// Add presets to the playlist recursively skipping duplicates.
-    pub fn add_preset_path(&self, preset_path: &PathBuf) {
-        self.playlist.add_path(preset_path.to_str().unwrap(), true);
-        println!("added preset path: {}", preset_path.to_str().unwrap());
-        println!("playlist size: {}", self.playlist.len());
+    /// Add presets from each of `preset_paths` in order, skipping duplicates.
+    pub fn add_preset_paths(&self, preset_paths: &[PathBuf]) {
+        for preset_path in preset_paths {
+            self.add_preset_path(preset_path);
+        }
+    }
+
+    /// Add presets from preset paths added while running. In favorites only
+    /// mode they are kept aside with the whole playlist, and only favorites
+    /// among them join the playlist.
+    pub fn add_new_preset_paths(&mut self, preset_paths: &[PathBuf]) {
+        if self.all_presets.is_none() {
+            self.add_preset_paths(preset_paths);
+            return;
+        }
+
+        let mut known: HashSet<PathBuf> = self
+            .playlist
+            .items()
+            .into_iter()
+            .chain(self.all_presets.iter().flatten().cloned())
+            .collect();
+        for preset_path in preset_paths {
+            let added = self.add_preset_files(preset_path_files(preset_path), &mut known);
+            println!(
+                "added {} presets from {} (playlist size: {})",
+                added.len(),
+                preset_path.display(),
+                self.playlist.len()
+            );
+        }
+    }
+
+    /// Remove the presets from `preset_paths` that none of the configured
+    /// preset paths cover anymore, e.g. after they were removed from the config
+    /// file.
+    pub fn remove_preset_paths(&mut self, preset_paths: &[PathBuf]) {
+        let is_removed = |app: &App, preset: &Path| {
+            preset_paths
+                .iter()
+                .any(|preset_path| preset_path_contains(preset_path, preset))
+                && !app.is_in_preset_paths(preset)
+        };
+
+        // remove from the back so the indices of the presets still to remove stay the same
+        let mut removed = 0;
+        for (index, preset) in self.playlist.items().iter().enumerate().rev() {
+            if is_removed(self, preset) {
+                self.playlist.remove(index as u32);
+                removed += 1;
+            }
+        }
+        if let Some(mut all_presets) = self.all_presets.take() {
+            let before = all_presets.len();
+            all_presets.retain(|preset| !is_removed(self, preset));
+            removed = removed.max(before - all_presets.len());
+            self.all_presets = Some(all_presets);
+        }
+
+        let paths: Vec<String> = preset_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        println!(
+            "removed {} presets from {} (playlist size: {})",
+            removed,
+            paths.join(", "),
+            self.playlist.len()
+        );
+    }
+
+    /// Add presets to the playlist skipping duplicates. `preset_path` may be a
+    /// directory, searched recursively, a preset file, or a glob pattern
+    /// matching either. Returns the number of presets added.
+    pub fn add_preset_path(&self, preset_path: &Path) -> u32 {
+        let pattern = preset_path.to_string_lossy();
+        let added = if is_glob(preset_path) {
+            match glob::glob(&pattern) {
+                Ok(matches) => matches
+                    .filter_map(Result::ok)
+                    .map(|path| self.add_preset_source(&path))
+                    .sum(),
+                Err(e) => {
+                    println!("Warning: invalid preset pattern {}: {}", pattern, e);
+                    return 0;
+                }
+            }
+        } else if preset_path.exists() {
+            self.add_preset_source(preset_path)
+        } else {
+            println!("Warning: preset path not found: {}", preset_path.display());
+            return 0;
+        };
+
+        println!(
+            "added {} presets from {} (playlist size: {})",
+            added,
+            preset_path.display(),
+            self.playlist.len()
+        );
+        added
+    }
+
+    /// Add a single preset directory or file.
+    fn add_preset_source(&self, path: &Path) -> u32 {
+        // resolve relative paths and links, so the same preset reached through
+        // different paths is still detected as a duplicate
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if path.is_dir() {
+            self.playlist.add_path(&path, true)
+        } else if self.blacklist.contains(&path) {
+            0
+        } else {
+            self.playlist.add_preset(&path) as u32
+        }
+    }
+
+    /// Remove blacklisted presets from the playlist. libprojectM adds whole
+    /// directories itself, so they can only be filtered out afterwards.
+    pub fn remove_blacklisted_presets(&self) {
+        let items = self.playlist.items();
+        let kept: Vec<PathBuf> = items
+            .iter()
+            .filter(|preset| !self.blacklist.contains(preset))
+            .cloned()
+            .collect();
+        if kept.len() < items.len() {
+            self.playlist.replace(&kept);
+            println!("skipped {} blacklisted presets", items.len() - kept.len());
+        }
+    }
+
+    /// Load the configured playlist file, drop blacklisted presets and apply
+    /// the playlist order, shuffle and favorites only modes. Call after adding
+    /// presets.
+    pub fn apply_playlist_options(&mut self) {
+        if let Some(playlist) = self.config.playlist.clone() {
+            self.load_playlist_file(&playlist);
+        }
+        self.remove_blacklisted_presets();
+
+        if let Some(order) = &self.config.playlist_order {
+            match PlaylistOrder::parse(order) {
+                Ok(order) => self.order_playlist(order),
+                Err(e) => println!("Warning: {}", e),
+            }
+        }
+
+        if let Some(shuffle) = self.config.shuffle {
+            self.playlist.set_shuffle(shuffle);
+            self.apply_preset_duration();
+        }
+
+        if self.config.favorites_only == Some(true) {
+            self.set_favorites_only(true);
+        }
+    }
+
+    /// Load presets and their options from a playlist file, putting them
+    /// ahead of the presets already in the playlist.
+    pub fn load_playlist_file(&mut self, path: &Path) {
+        if !path.exists() {
+            println!(
+                "Playlist file {} doesn't exist yet, saving the playlist will create it",
+                path.display()
+            );
+            return;
+        }
+        let entries = match file::load(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("Warning: {}", e);
+                return;
+            }
+        };
+
+        let mut items: Vec<PathBuf> = Vec::new();
+        for entry in entries {
+            if !entry.path.is_file() {
+                println!("Warning: preset not found: {}", entry.path.display());
+                continue;
+            }
+            let preset = entry.path.canonicalize().unwrap_or(entry.path);
+            if items.contains(&preset) {
+                continue;
+            }
+            if entry.options != PresetOptions::default() {
+                self.preset_options.insert(preset.clone(), entry.options);
+            }
+            items.push(preset);
+        }
+        let loaded = items.len();
+        for preset in self.playlist.items() {
+            if !items.contains(&preset) {
+                items.push(preset);
+            }
+        }
+        self.playlist.replace(&items);
+
+        println!(
+            "loaded {} presets from {} (playlist size: {})",
+            loaded,
+            path.display(),
+            self.playlist.len()
+        );
+        self.apply_preset_duration();
+    }
+
+    /// Write the presets in playlist order, with their options, to `path` or
+    /// else the configured playlist file. Returns the path written to.
+    pub fn save_playlist(&mut self, path: Option<&Path>) -> Result<PathBuf, String> {
+        let path = path
+            .or(self.config.playlist.as_deref())
+            .ok_or("no playlist file given or configured")?
+            .to_path_buf();
+
+        let entries: Vec<file::PlaylistEntry> = self
+            .playlist
+            .items()
+            .into_iter()
+            .map(|preset| file::PlaylistEntry {
+                options: self
+                    .preset_options
+                    .get(&preset)
+                    .cloned()
+                    .unwrap_or_default(),
+                path: preset,
+            })
+            .collect();
+        file::save(&path, &entries)?;
+
+        println!(
+            "Saved {} presets to playlist {}",
+            entries.len(),
+            path.display()
+        );
+        self.osd_message(format!("Saved playlist {}", path.display()));
+        Ok(path)
+    }
+
+    /// Reorder the presets in the playlist.
+    pub fn order_playlist(&mut self, order: PlaylistOrder) {
+        match order {
+            PlaylistOrder::Name => self.playlist.sort(true),
+            PlaylistOrder::Path => self.playlist.sort(false),
+            PlaylistOrder::Modified => {
+                let mut items = self.playlist.items();
+                // unreadable files go first
+                items.sort_by_cached_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+                self.playlist.replace(&items);
+            }
+            PlaylistOrder::Random => {
+                let mut items = self.playlist.items();
+                items.shuffle(&mut self.rng);
+                self.playlist.replace(&items);
+            }
+        }
+    }
+
+    /// Turn picking the next preset at random on or off. This uses libprojectM's
+    /// own RNG, so unlike the `random` order it isn't affected by the seed.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.playlist.set_shuffle(shuffle);
+        self.config.shuffle = Some(shuffle);
+        self.apply_preset_duration();
+        let message = if shuffle { "Shuffle on" } else { "Shuffle off" };
+        println!("{}", message);
+        self.osd_message(message.to_string());
+    }
+
+    /// Switch to the first preset in the playlist, if there is one.
+    pub fn playlist_play_first(&mut self) {
+        if !self.playlist.is_empty() {
+            self.playlist.set_position(0, true);
+        }
     }
 
     pub fn playlist_play_next(&mut self) {
-        self.playlist.play_next();
+        self.switch_requested = true;
+        self.playlist.play_next(true);
     }
     pub fn playlist_play_prev(&mut self) {
-        self.playlist.play_prev();
+        self.switch_requested = true;
+        self.playlist.play_prev(true);
     }
+    /// Play a random preset other than the current one, picked with the app's
+    /// (possibly seeded) RNG. With weighted random on, presets are picked in
+    /// proportion to their rating from the play statistics.
     pub fn playlist_play_random(&mut self) {
-        self.playlist.play_random();
+        self.switch_requested = true;
+        self.play_random(true);
+    }
+
+    /// Switch to a random preset, see [`App::playlist_play_random`].
+    pub fn play_random(&mut self, hard_cut: bool) {
+        let len = self.playlist.len();
+        if len < 2 {
+            return;
+        }
+        let position = self.playlist.position();
+        let index = match self.weighted_random_index() {
+            Some(index) => index,
+            None => {
+                let index = self.rng.random_range(0..len - 1);
+                if index >= position {
+                    index + 1
+                } else {
+                    index
+                }
+            }
+        };
+        self.playlist.set_position(index, hard_cut);
+    }
+
+    /// Index of a preset other than the current one, picked by rating, if
+    /// weighted random is on.
+    fn weighted_random_index(&mut self) -> Option<u32> {
+        if !self.config.weighted_random.unwrap_or(false) {
+            return None;
+        }
+        let position = self.playlist.position() as usize;
+        let weights: Vec<f64> = self
+            .playlist
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(index, preset)| {
+                if index == position {
+                    0.0
+                } else {
+                    self.stats.rating(preset)
+                }
+            })
+            .collect();
+        // fails if every other preset failed to load too often to have any weight left
+        let distribution = WeightedIndex::new(weights).ok()?;
+        Some(distribution.sample(&mut self.rng) as u32)
+    }
+
+    /// Lock or unlock the current preset. While locked, presets only switch
+    /// on request, not when the preset duration is up or on hard cuts.
+    pub fn set_preset_locked(&mut self, locked: bool) {
+        self.pm.set_preset_locked(locked);
+        let message = if locked {
+            "Preset locked"
+        } else {
+            "Preset unlocked"
+        };
+        println!("{}", message);
+        self.osd_message(message.to_string());
+    }
+
+    /// Whether `preset` was marked as a favorite, with the favorite key or in
+    /// a playlist file.
+    pub fn is_favorite(&self, preset: &Path) -> bool {
+        self.favorites.contains(preset)
+            || self
+                .preset_options
+                .get(preset)
+                .filter(|options| options.favorite)
+                .is_some()
+    }
+
+    /// Add the current preset to the favorites, or remove it if it's one.
+    pub fn toggle_favorite(&mut self) {
+        let preset = match self.playlist.current() {
+            Some(preset) => preset,
+            None => return,
+        };
+        let name = preset_name(&preset);
+        let message = match self.favorites.toggle(&preset) {
+            Ok(true) => format!("Added {} to favorites", name),
+            Ok(false) => format!("Removed {} from favorites", name),
+            Err(e) => format!("Could not save favorites: {}", e),
+        };
+        println!("{}", message);
+        self.osd_message(message);
+    }
+
+    /// Blacklist the current preset, so it's never played again, and switch
+    /// to the next one.
+    pub fn blacklist_current_preset(&mut self) {
+        let preset = match self.playlist.current() {
+            Some(preset) => preset,
+            None => return,
+        };
+        let message = match self.blacklist.add(&preset) {
+            Ok(()) => format!("Blacklisted {}", preset_name(&preset)),
+            Err(e) => format!("Could not save blacklist: {}", e),
+        };
+        println!("{}", message);
+        self.osd_message(message);
+
+        if let Some(all_presets) = self.all_presets.as_mut() {
+            all_presets.retain(|p| p != &preset);
+        }
+        let position = self.playlist.position();
+        self.playlist.remove(position);
+        if !self.playlist.is_empty() {
+            // rejected by the user, so its statistics count it as skipped
+            self.switch_requested = true;
+            self.playlist
+                .set_position(position.min(self.playlist.len() - 1), true);
+        }
+    }
+
+    /// Play only favorite presets, or go back to the whole playlist.
+    pub fn set_favorites_only(&mut self, favorites_only: bool) {
+        if favorites_only == self.all_presets.is_some() {
+            return;
+        }
+
+        if favorites_only {
+            let items = self.playlist.items();
+            // favorites from earlier runs first, then those marked in playlist files
+            let mut favorites: Vec<PathBuf> = Vec::new();
+            for preset in self.favorites.presets().iter().chain(&items) {
+                if self.is_favorite(preset)
+                    && preset.is_file()
+                    && !self.blacklist.contains(preset)
+                    && !favorites.contains(preset)
+                {
+                    favorites.push(preset.clone());
+                }
+            }
+            if favorites.is_empty() {
+                println!("No favorites to play");
+                self.osd_message("No favorites to play".to_string());
+                return;
+            }
+
+            let current = self.playlist.current();
+            self.playlist.replace(&favorites);
+            self.all_presets = Some(items);
+            if current
+                .filter(|preset| favorites.contains(preset))
+                .is_none()
+            {
+                self.playlist.set_position(0, true);
+            }
+        } else if let Some(items) = self.all_presets.take() {
+            self.playlist.replace(&items);
+        }
+
+        self.config.favorites_only = Some(favorites_only);
+        let message = if favorites_only {
+            "Playing favorites only"
+        } else {
+            "Playing all presets"
+        };
+        println!("{}", message);
+        self.osd_message(message.to_string());
+    }
+
+    /// Whether the app switches presets itself when their time is up, see
+    /// [`App::check_preset_duration`]: when any preset has a duration of its own,
+    /// as libprojectM only supports one duration for all presets, and when
+    /// shuffled presets are picked by rating, as libprojectM's shuffle picks
+    /// uniformly.
+    fn times_presets(&self) -> bool {
+        self.weighted_shuffle()
+            || self
+                .preset_options
+                .values()
+                .any(|options| options.duration.is_some())
+    }
+
+    /// Whether the presets switched to when their time is up are picked at
+    /// random weighted by rating.
+    fn weighted_shuffle(&self) -> bool {
+        self.config.weighted_random.unwrap_or(false) && self.playlist.shuffle()
+    }
+
+    /// Seconds presets without a duration of their own are played for.
+    pub fn preset_duration(&self) -> f64 {
+        match self.config.preset_duration {
+            Some(preset_duration) => preset_duration,
+            None if self.times_presets() => DEFAULT_PRESET_DURATION,
+            None => self.pm.get_preset_duration(),
+        }
+    }
+
+    /// Hand the preset duration to libprojectM, unless the app times presets
+    /// itself. Call after changing the preset duration, preset options, shuffle
+    /// or weighted random.
+    pub fn apply_preset_duration(&self) {
+        if self.times_presets() {
+            self.pm.set_preset_duration(APP_TIMED_PRESET_DURATION);
+        } else if let Some(preset_duration) = self.config.preset_duration {
+            self.pm.set_preset_duration(preset_duration);
+        }
+    }
+
+    /// Switch to the next preset with a transition once the current one has
+    /// played for its duration, if the app times presets. Call once per frame,
+    /// after [`App::handle_preset_switches`].
+    pub fn check_preset_duration(&mut self) {
+        if !self.times_presets() || self.pm.get_preset_locked() {
+            return;
+        }
+        let duration = self
+            .playlist
+            .current()
+            .and_then(|preset| self.preset_options.get(&preset))
+            .and_then(|options| options.duration)
+            .unwrap_or_else(|| self.preset_duration());
+        let played = self.clock.now().saturating_sub(self.preset_started);
+        if played.as_secs_f64() >= duration {
+            if self.weighted_shuffle() {
+                self.play_random(false);
+            } else {
+                self.playlist.play_next(false);
+            }
+            // don't switch again before the switch is handled
+            self.preset_started = self.clock.now();
+        }
+    }
+
+    /// React to presets switched since the last frame. Call once per frame.
+    pub fn handle_preset_switches(&mut self) {
+        let now = self.clock.now();
+        for index in self.playlist.take_switches() {
+            self.preset_started = now;
+            let preset = self.playlist.item(index);
+            self.stats
+                .preset_started(preset.clone(), now, self.switch_requested);
+            println!(
+                "Playing preset {}: {}",
+                index,
+                preset
+                    .as_ref()
+                    .map_or("unknown".to_string(), |p| p.display().to_string())
+            );
+            let name = preset
+                .as_ref()
+                .map_or("unknown".to_string(), |p| preset_name(p));
+            self.osd_message(format!("{} ({}/{})", name, index + 1, self.playlist.len()));
+
+            #[cfg(unix)]
+            self.send_ipc_preset_switched(index);
+        }
+
+        for (preset, message) in self.playlist.take_failures() {
+            println!(
+                "Warning: could not load preset {}: {}",
+                preset.display(),
+                message
+            );
+            self.stats.preset_failed(&preset);
+        }
+        // switches are reported in the frame they're requested in
+        self.switch_requested = false;
+        if let Err(e) = self.stats.save_if_due(now) {
+            println!("Warning: could not save statistics: {}", e);
+        }
+    }
+}
+
+/// File name of `preset`, for messages.
+fn preset_name(preset: &Path) -> String {
+    preset
+        .file_name()
+        .map_or(preset.to_string_lossy(), |name| name.to_string_lossy())
+        .into_owned()
+}
+
+/// Directories holding the presets from `preset_paths`, which may be
+/// directories, preset files or glob patterns as for [`App::add_preset_path`].
+pub fn preset_dirs(preset_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for preset_path in preset_paths {
+        let pattern = preset_path.to_string_lossy();
+        let paths: Vec<PathBuf> = if is_glob(preset_path) {
+            glob::glob(&pattern)
+                .map(|matches| matches.filter_map(Result::ok).collect())
+                .unwrap_or_default()
+        } else {
+            vec![preset_path.clone()]
+        };
+
+        for path in paths {
+            let dir = if path.is_dir() {
+                Some(path)
+            } else if path.is_file() {
+                path.parent().map(Path::to_path_buf)
+            } else {
+                None
+            };
+            if let Some(dir) = dir {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+    }
+    dirs
+}
+
+/// Whether `path` is a glob pattern: it contains glob wildcards and isn't an
+/// existing file or directory, as preset names often contain `[...]`.
+pub fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '[']) && !path.exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse_playlist_order() {
+        assert_eq!(PlaylistOrder::parse("name"), Ok(PlaylistOrder::Name));
+        assert_eq!(PlaylistOrder::parse("Path"), Ok(PlaylistOrder::Path));
+        assert_eq!(PlaylistOrder::parse("mtime"), Ok(PlaylistOrder::Modified));
+        assert_eq!(PlaylistOrder::parse("random"), Ok(PlaylistOrder::Random));
+        assert!(PlaylistOrder::parse("size").is_err());
+    }
+
+    #[test]
+    fn test_is_glob() {
+        assert!(is_glob(Path::new("presets/*.milk")));
+        assert!(is_glob(Path::new("presets/[a-c]*")));
+        assert!(!is_glob(Path::new("presets/curated")));
+
+        // existing paths are taken as they are
+        let temp = TempDir::new();
+        let preset = temp
+            .path()
+            .join("Flexi - jelly [stahls jelly 4.5 finish].milk");
+        fs::write(&preset, "").unwrap();
+        assert!(!is_glob(&preset));
     }
 }
@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Per-preset settings stored in JSON playlist files.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PresetOptions {
+    /// Seconds to play the preset for, instead of the preset duration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub favorite: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub options: PresetOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonPlaylist {
+    presets: Vec<PlaylistEntry>,
+}
+
+/// Whether `path` is written as JSON rather than M3U.
+fn is_json(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext.eq_ignore_ascii_case("json"))
+}
+
+/// Read a playlist file: JSON if it ends in `.json`, otherwise M3U with one
+/// preset path per line. Relative paths are relative to the file.
+pub fn load(path: &Path) -> Result<Vec<PlaylistEntry>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("could not read playlist {}: {}", path.display(), e))?;
+
+    let mut entries = if is_json(path) {
+        serde_json::from_str::<JsonPlaylist>(&contents)
+            .map_err(|e| format!("invalid playlist {}: {}", path.display(), e))?
+            .presets
+    } else {
+        contents
+            .lines()
+            .map(str::trim)
+            // comments and extended M3U directives
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| PlaylistEntry {
+                path: PathBuf::from(line),
+                options: PresetOptions::default(),
+            })
+            .collect()
+    };
+
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    for entry in &mut entries {
+        if entry.path.is_relative() {
+            entry.path = base.join(&entry.path);
+        }
+    }
+    Ok(entries)
+}
+
+/// Write a playlist file in the format picked by its extension, as for [`load`].
+/// M3U files only keep the paths.
+pub fn save(path: &Path, entries: &[PlaylistEntry]) -> Result<(), String> {
+    let contents = if is_json(path) {
+        let playlist = JsonPlaylist {
+            presets: entries.to_vec(),
+        };
+        serde_json::to_string_pretty(&playlist).map_err(|e| e.to_string())? + "\n"
+    } else {
+        let mut contents = "#EXTM3U\n".to_string();
+        for entry in entries {
+            contents.push_str(&entry.path.to_string_lossy());
+            contents.push('\n');
+        }
+        contents
+    };
+
+    fs::write(path, contents)
+        .map_err(|e| format!("could not write playlist {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_load_and_save() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+
+        let m3u = dir.join("set.m3u");
+        fs::write(
+            &m3u,
+            "#EXTM3U\n# opener\nintro.milk\n\n/presets/drop.milk\n",
+        )
+        .unwrap();
+        let mut entries = load(&m3u).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.path.clone()).collect::<Vec<_>>(),
+            vec![dir.join("intro.milk"), PathBuf::from("/presets/drop.milk")]
+        );
+
+        entries[1].options = PresetOptions {
+            duration: Some(45.0),
+            favorite: true,
+        };
+        let json = dir.join("set.json");
+        save(&json, &entries).unwrap();
+        assert_eq!(load(&json).unwrap(), entries);
+
+        // M3U only keeps the paths
+        save(&m3u, &entries).unwrap();
+        assert_eq!(load(&m3u).unwrap()[1].options, PresetOptions::default());
+
+        fs::write(
+            &json,
+            r#"{"presets": [{"path": "a.milk", "duration": "long"}]}"#,
+        )
+        .unwrap();
+        assert!(load(&json).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::{Path, PathBuf};
+
+use projectm_sys as ffi;
+
+use crate::app::ProjectMWrapped;
+
+/// Preset playlist connected to a projectM instance.
+///
+/// Wraps the libprojectM playlist API directly, since the `projectm` crate's
+/// playlist doesn't expose positions and picks random presets with its own,
+/// unseeded RNG.
+pub struct Playlist {
+    playlist: *mut ffi::projectm_playlist,
+    // indices of presets switched to, filled in by libprojectM's callback
+    switches: Box<RefCell<Vec<u32>>>,
+    // presets that failed to load, with the error, filled in the same way
+    failures: Box<RefCell<Vec<(PathBuf, String)>>>,
+    // keep projectM alive for as long as the playlist is connected to it
+    _pm: ProjectMWrapped,
+}
+
+unsafe extern "C" fn on_preset_switched(_is_hard_cut: bool, index: c_uint, user_data: *mut c_void) {
+    let switches = &*(user_data as *const RefCell<Vec<u32>>);
+    if let Ok(mut switches) = switches.try_borrow_mut() {
+        switches.push(index);
+    }
+}
+
+unsafe extern "C" fn on_preset_switch_failed(
+    preset_filename: *const c_char,
+    message: *const c_char,
+    user_data: *mut c_void,
+) {
+    let failures = &*(user_data as *const RefCell<Vec<(PathBuf, String)>>);
+    if let Ok(mut failures) = failures.try_borrow_mut() {
+        failures.push((
+            PathBuf::from(
+                CStr::from_ptr(preset_filename)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            CStr::from_ptr(message).to_string_lossy().into_owned(),
+        ));
+    }
+}
+
+impl Playlist {
+    /// Create a playlist that switches presets on `pm`.
+    pub fn create(pm: &ProjectMWrapped) -> Self {
+        let instance = pm.get_instance();
+        let instance = instance.borrow();
+        let playlist = unsafe { ffi::projectm_playlist_create(*instance) };
+
+        let switches = Box::new(RefCell::new(Vec::new()));
+        let failures = Box::new(RefCell::new(Vec::new()));
+        unsafe {
+            ffi::projectm_playlist_set_preset_switched_event_callback(
+                playlist,
+                Some(on_preset_switched),
+                &*switches as *const RefCell<Vec<u32>> as *mut c_void,
+            );
+            ffi::projectm_playlist_set_preset_switch_failed_event_callback(
+                playlist,
+                Some(on_preset_switch_failed),
+                &*failures as *const RefCell<Vec<(PathBuf, String)>> as *mut c_void,
+            );
+        }
+
+        Self {
+            playlist,
+            switches,
+            failures,
+            _pm: pm.clone(),
+        }
+    }
+
+    pub fn len(&self) -> u32 {
+        unsafe { ffi::projectm_playlist_size(self.playlist) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add presets in `path`, skipping duplicates. Returns the number of presets added.
+    pub fn add_path(&self, path: &Path, recursive: bool) -> u32 {
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        unsafe { ffi::projectm_playlist_add_path(self.playlist, c_path.as_ptr(), recursive, false) }
+    }
+
+    /// Add a single preset file, unless it's already in the playlist. Returns
+    /// whether it was added.
+    pub fn add_preset(&self, path: &Path) -> bool {
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        unsafe { ffi::projectm_playlist_add_preset(self.playlist, c_path.as_ptr(), false) }
+    }
+
+    /// Remove the preset at `index`. Returns whether there was one.
+    pub fn remove(&self, index: u32) -> bool {
+        unsafe { ffi::projectm_playlist_remove_preset(self.playlist, index) }
+    }
+
+    /// Path of the preset at `index`.
+    pub fn item(&self, index: u32) -> Option<PathBuf> {
+        unsafe {
+            let item = ffi::projectm_playlist_item(self.playlist, index);
+            if item.is_null() {
+                return None;
+            }
+            let path = PathBuf::from(CStr::from_ptr(item).to_string_lossy().into_owned());
+            ffi::projectm_playlist_free_string(item);
+            Some(path)
+        }
+    }
+
+    /// Paths of all presets, in playlist order.
+    pub fn items(&self) -> Vec<PathBuf> {
+        unsafe {
+            let items = ffi::projectm_playlist_items(self.playlist, 0, self.len());
+            if items.is_null() {
+                return Vec::new();
+            }
+            let mut paths = Vec::new();
+            let mut item = items;
+            while !(*item).is_null() {
+                paths.push(PathBuf::from(
+                    CStr::from_ptr(*item).to_string_lossy().into_owned(),
+                ));
+                item = item.add(1);
+            }
+            ffi::projectm_playlist_free_string_array(items);
+            paths
+        }
+    }
+
+    /// Replace all presets with `paths`, in that order.
+    pub fn replace(&self, paths: &[PathBuf]) {
+        let c_paths: Vec<CString> = paths
+            .iter()
+            .map(|path| CString::new(path.to_string_lossy().as_bytes()).unwrap())
+            .collect();
+        let mut pointers: Vec<*const c_char> = c_paths.iter().map(|path| path.as_ptr()).collect();
+        unsafe {
+            ffi::projectm_playlist_clear(self.playlist);
+            ffi::projectm_playlist_add_presets(
+                self.playlist,
+                pointers.as_mut_ptr(),
+                pointers.len() as u32,
+                true,
+            );
+        }
+    }
+
+    /// Sort all presets in ascending order, by file name only or by full path.
+    pub fn sort(&self, by_file_name: bool) {
+        let predicate = if by_file_name {
+            ffi::projectm_playlist_sort_predicate_SORT_PREDICATE_FILENAME_ONLY
+        } else {
+            ffi::projectm_playlist_sort_predicate_SORT_PREDICATE_FULL_PATH
+        };
+        unsafe {
+            ffi::projectm_playlist_sort(
+                self.playlist,
+                0,
+                self.len(),
+                predicate,
+                ffi::projectm_playlist_sort_order_SORT_ORDER_ASCENDING,
+            )
+        }
+    }
+
+    /// Whether libprojectM picks the next preset at random.
+    pub fn shuffle(&self) -> bool {
+        unsafe { ffi::projectm_playlist_get_shuffle(self.playlist) }
+    }
+
+    pub fn set_shuffle(&self, shuffle: bool) {
+        unsafe { ffi::projectm_playlist_set_shuffle(self.playlist, shuffle) }
+    }
+
+    /// Path of the current preset.
+    pub fn current(&self) -> Option<PathBuf> {
+        if self.is_empty() {
+            return None;
+        }
+        self.item(self.position())
+    }
+
+    /// Indices of the presets switched to since the last call, oldest first.
+    pub fn take_switches(&self) -> Vec<u32> {
+        self.switches.take()
+    }
+
+    /// Presets that failed to load since the last call, with the error.
+    pub fn take_failures(&self) -> Vec<(PathBuf, String)> {
+        self.failures.take()
+    }
+
+    /// Index of the current preset.
+    pub fn position(&self) -> u32 {
+        unsafe { ffi::projectm_playlist_get_position(self.playlist) }
+    }
+
+    /// Switch to the preset at `index`. Returns the new position.
+    pub fn set_position(&self, index: u32, hard_cut: bool) -> u32 {
+        unsafe { ffi::projectm_playlist_set_position(self.playlist, index, hard_cut) }
+    }
+
+    pub fn play_next(&self, hard_cut: bool) -> u32 {
+        unsafe { ffi::projectm_playlist_play_next(self.playlist, hard_cut) }
+    }
+
+    pub fn play_prev(&self, hard_cut: bool) -> u32 {
+        unsafe { ffi::projectm_playlist_play_previous(self.playlist, hard_cut) }
+    }
+}
+
+impl Drop for Playlist {
+    fn drop(&mut self) {
+        // also unregisters the callbacks pointing at `switches` and `failures`
+        unsafe { ffi::projectm_playlist_destroy(self.playlist) }
+    }
+}
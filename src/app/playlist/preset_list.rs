@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::file::{self, PlaylistEntry};
+
+/// Preset paths kept across runs in an M3U file, such as the favorites.
+pub struct PresetList {
+    /// File the list is kept in, or `None` to only keep it for this run.
+    path: Option<PathBuf>,
+    presets: Vec<PathBuf>,
+}
+
+impl PresetList {
+    /// Load the list kept in `path`. A missing file is an empty list.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let presets = match &path {
+            Some(path) if path.exists() => match file::load(path) {
+                Ok(entries) => entries.into_iter().map(|entry| entry.path).collect(),
+                Err(e) => {
+                    println!("Warning: {}", e);
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        };
+        Self { path, presets }
+    }
+
+    /// Presets in the order they were added.
+    pub fn presets(&self) -> &[PathBuf] {
+        &self.presets
+    }
+
+    pub fn contains(&self, preset: &Path) -> bool {
+        self.presets.iter().any(|p| p == preset)
+    }
+
+    /// Add `preset` and save the list, unless it's already in it.
+    pub fn add(&mut self, preset: &Path) -> Result<(), String> {
+        if self.contains(preset) {
+            return Ok(());
+        }
+        self.presets.push(preset.to_path_buf());
+        self.save()
+    }
+
+    /// Add `preset`, or remove it if it's already in the list, and save the
+    /// list. Returns whether the preset is in the list now.
+    pub fn toggle(&mut self, preset: &Path) -> Result<bool, String> {
+        if self.contains(preset) {
+            self.presets.retain(|p| p != preset);
+            self.save()?;
+            Ok(false)
+        } else {
+            self.add(preset)?;
+            Ok(true)
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+        }
+        let entries: Vec<PlaylistEntry> = self
+            .presets
+            .iter()
+            .map(|preset| PlaylistEntry {
+                path: preset.clone(),
+                options: Default::default(),
+            })
+            .collect();
+        file::save(path, &entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_preset_list() {
+        let temp = TempDir::new();
+        // the list creates its directory when saving
+        let path = temp.path().join("lists/favorites.m3u");
+        let (a, b) = (Path::new("/presets/a.milk"), Path::new("/presets/b.milk"));
+
+        let mut list = PresetList::load(Some(path.clone()));
+        assert!(list.presets().is_empty());
+        assert_eq!(list.toggle(a), Ok(true));
+        list.add(b).unwrap();
+        list.add(b).unwrap();
+        assert_eq!(list.presets(), [a, b]);
+
+        // kept across runs
+        let mut list = PresetList::load(Some(path.clone()));
+        assert_eq!(list.presets(), [a, b]);
+        assert_eq!(list.toggle(a), Ok(false));
+        assert!(!PresetList::load(Some(path)).contains(a));
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::app::playlist::{is_glob, preset_dirs, preset_name};
+use crate::app::watcher::FileWatcher;
+use crate::app::App;
+
+/// Time to wait after the last change before handling changes, so editors
+/// and file copies are done writing.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Watches the preset directories to pick up edited, new and deleted presets.
+pub struct PresetWatcher {
+    watcher: FileWatcher,
+    /// Paths changed since they were last handled.
+    changed: BTreeSet<PathBuf>,
+    changed_at: Option<Instant>,
+}
+
+impl App {
+    /// Watch the directories holding the presets from the preset paths.
+    pub fn watch_presets(&mut self) {
+        let preset_paths = self.config.preset_path.clone().unwrap_or_default();
+        let mut watcher = match FileWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                println!("Warning: preset watching disabled: {}", e);
+                return;
+            }
+        };
+        for dir in preset_dirs(&preset_paths) {
+            let dir = dir.canonicalize().unwrap_or(dir);
+            match watcher.watch(&dir, true) {
+                Ok(()) => println!("Watching {} for preset changes", dir.display()),
+                Err(e) => println!("Warning: {}", e),
+            }
+        }
+        self.preset_watcher = Some(PresetWatcher {
+            watcher,
+            changed: BTreeSet::new(),
+            changed_at: None,
+        });
+    }
+
+    /// Reload the current preset when its file changes, and add and remove
+    /// presets as their files appear and disappear. Call once per frame.
+    pub fn process_preset_changes(&mut self) {
+        let watcher = match self.preset_watcher.as_mut() {
+            Some(watcher) => watcher,
+            None => return,
+        };
+
+        for event in watcher.watcher.poll() {
+            for path in event.paths {
+                watcher.changed.insert(path);
+                watcher.changed_at = Some(Instant::now());
+            }
+        }
+        match watcher.changed_at {
+            Some(changed_at) if changed_at.elapsed() >= SETTLE_TIME => {
+                watcher.changed_at = None;
+            }
+            _ => return,
+        }
+
+        let changed: Vec<PathBuf> = std::mem::take(&mut watcher.changed).into_iter().collect();
+        self.handle_preset_changes(&changed);
+    }
+
+    /// Reload, add and remove presets for a batch of changed paths. New presets
+    /// get the same filters as at startup: blacklisted ones are skipped, and in
+    /// favorites only mode only favorites join the playlist.
+    fn handle_preset_changes(&mut self, changed: &[PathBuf]) {
+        // the playlist is copied over FFI, so only once for the whole batch
+        let items = self.playlist.items();
+        let mut known: HashSet<PathBuf> = items
+            .iter()
+            .chain(self.all_presets.iter().flatten())
+            .cloned()
+            .collect();
+        let current = self.playlist.current();
+
+        let mut added: Vec<PathBuf> = Vec::new();
+        let mut removed: HashSet<&Path> = HashSet::new();
+        for path in changed {
+            if path.is_dir() {
+                // a directory of presets copied in at once
+                if self.is_in_preset_paths(path) {
+                    added.extend(preset_files(path));
+                }
+            } else if path.is_file() {
+                if !is_preset_file(path) {
+                    continue;
+                }
+                if !known.contains(path) {
+                    if self.is_in_preset_paths(path) {
+                        added.push(path.clone());
+                    }
+                } else if current.as_deref() == Some(path) {
+                    // projectM expects a NUL-terminated path
+                    self.pm
+                        .load_preset_file(&format!("{}\0", path.display()), false);
+                    println!("Reloaded preset {}", path.display());
+                    self.osd_message(format!("Reloaded {}", preset_name(path)));
+                }
+            } else {
+                // removed, or a whole directory of presets removed
+                removed.insert(path);
+            }
+        }
+
+        if !removed.is_empty() {
+            let is_removed = |preset: &Path| preset.ancestors().any(|path| removed.contains(path));
+            // remove from the back so the indices of the presets still to remove stay the same
+            let mut count = 0;
+            for (index, item) in items.iter().enumerate().rev() {
+                if is_removed(item) {
+                    self.playlist.remove(index as u32);
+                    count += 1;
+                }
+            }
+            if let Some(all_presets) = self.all_presets.as_mut() {
+                let before = all_presets.len();
+                all_presets.retain(|preset| !is_removed(preset));
+                count = count.max(before - all_presets.len());
+            }
+            if count > 0 {
+                println!("Removed {} presets", count);
+            }
+        }
+
+        let new_presets = self.add_preset_files(added, &mut known);
+        match new_presets.as_slice() {
+            [] => {}
+            [preset] => println!("Added preset {}", preset.display()),
+            presets => println!("Added {} presets", presets.len()),
+        }
+    }
+
+    /// Add preset files that aren't in `known` yet, the presets in the
+    /// playlist and those kept aside, and return the ones added. Blacklisted
+    /// presets are skipped, and in favorites only mode all presets are kept
+    /// aside and only favorites join the playlist.
+    pub fn add_preset_files(
+        &mut self,
+        presets: Vec<PathBuf>,
+        known: &mut HashSet<PathBuf>,
+    ) -> Vec<PathBuf> {
+        let mut new_presets: Vec<PathBuf> = Vec::new();
+        for path in presets {
+            let path = path.canonicalize().unwrap_or(path);
+            if self.blacklist.contains(&path) || !known.insert(path.clone()) {
+                continue;
+            }
+            // in favorites only mode the whole playlist is kept aside, and only
+            // favorites play
+            let plays = self.all_presets.is_none() || self.is_favorite(&path);
+            if let Some(all_presets) = self.all_presets.as_mut() {
+                all_presets.push(path.clone());
+            }
+            if !plays || self.playlist.add_preset(&path) {
+                new_presets.push(path);
+            }
+        }
+        new_presets
+    }
+
+    /// Whether `path` is one of the preset paths, or inside one of them, so
+    /// appearing there adds it to the playlist.
+    pub fn is_in_preset_paths(&self, path: &Path) -> bool {
+        self.config
+            .preset_path
+            .iter()
+            .flatten()
+            .any(|preset_path| preset_path_contains(preset_path, path))
+    }
+}
+
+/// Whether `preset_path`, a directory, preset file or glob pattern as for
+/// [`App::add_preset_path`], covers `path`.
+pub fn preset_path_contains(preset_path: &Path, path: &Path) -> bool {
+    if is_glob(preset_path) {
+        let pattern = absolute_pattern(preset_path);
+        return matches!(glob::Pattern::new(&pattern), Ok(pattern) if pattern.matches_path(path));
+    }
+    let preset_path = preset_path
+        .canonicalize()
+        .unwrap_or_else(|_| preset_path.to_path_buf());
+    path.starts_with(preset_path)
+}
+
+/// `pattern` as an absolute glob pattern, to match the absolute paths file
+/// watching reports: relative patterns are taken from the working directory,
+/// and the directories before the first wildcard are resolved like the watched
+/// directories are.
+fn absolute_pattern(pattern: &Path) -> String {
+    let pattern = match std::env::current_dir() {
+        Ok(dir) => dir.join(pattern),
+        Err(_) => pattern.to_path_buf(),
+    };
+    let mut dir = PathBuf::new();
+    let mut rest = PathBuf::new();
+    for component in pattern.components() {
+        let wildcard = component
+            .as_os_str()
+            .to_string_lossy()
+            .contains(['*', '?', '[']);
+        if wildcard || !rest.as_os_str().is_empty() {
+            rest.push(component);
+        } else {
+            dir.push(component);
+        }
+    }
+    let dir = dir.canonicalize().unwrap_or(dir);
+    // the resolved directory is matched literally
+    let mut absolute = glob::Pattern::escape(&dir.to_string_lossy());
+    if !absolute.ends_with(std::path::MAIN_SEPARATOR) {
+        absolute.push(std::path::MAIN_SEPARATOR);
+    }
+    absolute + &rest.to_string_lossy()
+}
+
+/// Preset files covered by `preset_path`, a directory, preset file or glob
+/// pattern as for [`App::add_preset_path`].
+pub fn preset_path_files(preset_path: &Path) -> Vec<PathBuf> {
+    let sources: Vec<PathBuf> = if is_glob(preset_path) {
+        match glob::glob(&preset_path.to_string_lossy()) {
+            Ok(matches) => matches.filter_map(Result::ok).collect(),
+            Err(_) => Vec::new(),
+        }
+    } else {
+        vec![preset_path.to_path_buf()]
+    };
+    sources
+        .into_iter()
+        .flat_map(|source| {
+            if source.is_dir() {
+                preset_files(&source)
+            } else if source.is_file() {
+                vec![source]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Preset files in `dir` and below, in path order.
+fn preset_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return files,
+    };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if path.is_dir() {
+            files.extend(preset_files(&path));
+        } else if is_preset_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Whether `path` looks like a preset file, by its extension.
+fn is_preset_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some(ext) if ext.eq_ignore_ascii_case("milk") || ext.eq_ignore_ascii_case("prjm")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_preset_path_contains() {
+        let presets = Path::new("/presets/curated");
+        assert!(preset_path_contains(
+            presets,
+            Path::new("/presets/curated/new/a.milk")
+        ));
+        assert!(!preset_path_contains(
+            presets,
+            Path::new("/presets/curated2/a.milk")
+        ));
+        let pattern = Path::new("/presets/new/*.milk");
+        assert!(preset_path_contains(
+            pattern,
+            Path::new("/presets/new/a.milk")
+        ));
+        assert!(!preset_path_contains(
+            pattern,
+            Path::new("/presets/new/a.prjm")
+        ));
+
+        // relative patterns are taken from the working directory, which is the
+        // package root when testing
+        let preset = Path::new("test-data/presets/reactive.milk")
+            .canonicalize()
+            .unwrap();
+        assert!(preset_path_contains(
+            Path::new("test-data/presets/*.milk"),
+            &preset
+        ));
+        assert!(preset_path_contains(
+            Path::new("./test-data/*/*.milk"),
+            &preset
+        ));
+        assert!(!preset_path_contains(
+            Path::new("test-data/presets/*.prjm"),
+            &preset
+        ));
+    }
+
+    #[test]
+    fn test_preset_files() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("pack/sub")).unwrap();
+        for file in [
+            "pack/b.milk",
+            "pack/a.prjm",
+            "pack/notes.txt",
+            "pack/sub/c.milk",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(
+            preset_files(&dir.join("pack")),
+            [
+                dir.join("pack/a.prjm"),
+                dir.join("pack/b.milk"),
+                dir.join("pack/sub/c.milk")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_preset_file() {
+        assert!(is_preset_file(Path::new("a.milk")));
+        assert!(is_preset_file(Path::new("b.PRJM")));
+        assert!(!is_preset_file(Path::new("a.milk.swp")));
+    }
+}
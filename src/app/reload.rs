@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::app::config::{parse_duration, parse_mesh_size, Config};
+use crate::app::keybindings::Keybindings;
+use crate::app::midi::MidiMap;
+use crate::app::playlist::PlaylistOrder;
+use crate::app::watcher::FileWatcher;
+use crate::app::App;
+
+/// Time to wait after the last change to the config file before reading it,
+/// so editors are done writing it.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Reads the configuration the same way as at startup, merging the config
+/// file with the command line and environment.
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config, String>>;
+
+/// Watches the config file to apply changes to it while running.
+pub struct ConfigReloader {
+    path: PathBuf,
+    watcher: FileWatcher,
+    loader: ConfigLoader,
+    /// The configuration as last read, to find what changed in the file.
+    loaded: Config,
+    /// When the file last changed, if it hasn't been read since.
+    changed_at: Option<Instant>,
+}
+
+impl App {
+    /// Watch the config file at `path` and apply changes to it while running.
+    /// `loader` reads the configuration the same way as at startup.
+    pub fn watch_config(&mut self, path: &Path, loader: ConfigLoader) {
+        // editors often replace the file rather than writing it, so watch its directory
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let watcher = FileWatcher::new().and_then(|mut watcher| {
+            watcher.watch(dir, false)?;
+            Ok(watcher)
+        });
+        match watcher {
+            Ok(watcher) => {
+                println!("Watching {} for changes", path.display());
+                self.config_reloader = Some(ConfigReloader {
+                    path: path.to_path_buf(),
+                    watcher,
+                    loader,
+                    loaded: self.config.clone(),
+                    changed_at: None,
+                });
+            }
+            Err(e) => println!("Warning: config reloading disabled: {}", e),
+        }
+    }
+
+    /// Reload the config file once it has changed and settled, applying what
+    /// changed in it. Call once per frame.
+    pub fn process_config_changes(&mut self) {
+        let reloader = match self.config_reloader.as_mut() {
+            Some(reloader) => reloader,
+            None => return,
+        };
+
+        let file_name = reloader.path.file_name();
+        let changed = reloader
+            .watcher
+            .poll()
+            .iter()
+            .any(|event| event.paths.iter().any(|p| p.file_name() == file_name));
+        if changed {
+            reloader.changed_at = Some(Instant::now());
+        }
+        match reloader.changed_at {
+            Some(changed_at) if changed_at.elapsed() >= SETTLE_TIME => {
+                reloader.changed_at = None;
+            }
+            _ => return,
+        }
+
+        let config = match (reloader.loader)() {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Warning: could not reload config: {}", e);
+                self.osd_message("Could not reload config".to_string());
+                return;
+            }
+        };
+        let loaded = std::mem::replace(&mut reloader.loaded, config.clone());
+        self.apply_config_changes(&loaded, &config);
+    }
+
+    /// Apply the settings that differ between `old` and `new`, both read from
+    /// the config file, on top of the running configuration. Settings changed
+    /// at runtime, e.g. by remote commands, stay as they are unless they also
+    /// changed in the file.
+    fn apply_config_changes(&mut self, old: &Config, new: &Config) {
+        let mut changed: Vec<&str> = Vec::new();
+        let mut needs_restart: Vec<&str> = Vec::new();
+
+        // copy a changed setting into the running configuration and apply it;
+        // settings removed from the file keep their current value
+        macro_rules! update {
+            ($field:ident) => {
+                update!($field, |_| {})
+            };
+            ($field:ident, |$value:pat_param| $apply:expr) => {
+                if new.$field != old.$field {
+                    changed.push(stringify!($field));
+                    if let Some(value) = &new.$field {
+                        self.config.$field = Some(value.clone());
+                        let $value = value.clone();
+                        $apply;
+                    }
+                }
+            };
+        }
+        macro_rules! restart {
+            ($($field:ident),*) => {
+                $(if new.$field != old.$field {
+                    needs_restart.push(stringify!($field));
+                })*
+            };
+        }
+
+        update!(beat_sensitivity, |value| self
+            .pm
+            .set_beat_sensitivity(value));
+        update!(preset_duration, |_| self.apply_preset_duration());
+        update!(hard_cut_enabled, |value| self
+            .pm
+            .set_hard_cut_enabled(value));
+        update!(hard_cut_duration, |value| self
+            .pm
+            .set_hard_cut_duration(value));
+        update!(hard_cut_sensitivity, |value| self
+            .pm
+            .set_hard_cut_sensitivity(value));
+        update!(soft_cut_duration, |value| self
+            .pm
+            .set_soft_cut_duration(value));
+        update!(aspect_correction, |value| self
+            .pm
+            .set_aspect_correction(value));
+        update!(easter_egg, |value| self.pm.set_easter_egg(value));
+        update!(mesh_size, |value| match parse_mesh_size(&value) {
+            Ok((mesh_x, mesh_y)) => self.pm.set_mesh_size(mesh_x, mesh_y),
+            Err(e) => println!("Warning: {}", e),
+        });
+
+        // presets from added paths join the playlist and those from removed paths
+        // leave it, and the preset watcher follows the new paths
+        update!(preset_path, |value| {
+            let old_paths = old.preset_path.clone().unwrap_or_default();
+            let added: Vec<_> = value
+                .iter()
+                .filter(|path| !old_paths.contains(path))
+                .cloned()
+                .collect();
+            let removed: Vec<_> = old_paths
+                .iter()
+                .filter(|path| !value.contains(path))
+                .cloned()
+                .collect();
+            if !removed.is_empty() {
+                self.remove_preset_paths(&removed);
+            }
+            self.add_new_preset_paths(&added);
+            self.remove_blacklisted_presets();
+            if self.preset_watcher.is_some() {
+                self.watch_presets();
+            }
+        });
+        update!(texture_path);
+        if new.preset_path != old.preset_path || new.texture_path != old.texture_path {
+            self.apply_texture_paths(&self.config);
+        }
+        update!(playlist, |value| self.load_playlist_file(&value));
+        update!(playlist_order, |value| match PlaylistOrder::parse(&value) {
+            Ok(order) => self.order_playlist(order),
+            Err(e) => println!("Warning: {}", e),
+        });
+        update!(shuffle, |value| self.set_shuffle(value));
+        update!(favorites_only, |value| self.set_favorites_only(value));
+        update!(weighted_random, |_| self.apply_preset_duration());
+
+        update!(
+            osd_duration,
+            |value| match parse_duration("osd_duration", value) {
+                Ok(duration) => {
+                    if let Some(osd) = self.osd.as_mut() {
+                        osd.set_message_duration(duration);
+                    }
+                }
+                Err(e) => println!("Warning: {}", e),
+            }
+        );
+        update!(keybindings, |value| self.keybindings =
+            Keybindings::new(&value));
+        update!(midi_learn);
+        update!(midi_notes);
+        update!(midi_controllers);
+        if self.midi_map.is_some()
+            && (new.midi_notes != old.midi_notes || new.midi_controllers != old.midi_controllers)
+        {
+            self.midi_map = Some(MidiMap::new(
+                &self.config.midi_notes.clone().unwrap_or_default(),
+                &self.config.midi_controllers.clone().unwrap_or_default(),
+            ));
+        }
+
+        restart!(
+            frame_rate,
+            audio_source,
+            audio_input,
+            audio_file,
+            width,
+            height,
+            headless,
+            frames,
+            output_dir,
+            fixed_step,
+            seed,
+            ipc_socket,
+            osc_port,
+            osc_bind,
+            osc_addresses,
+            midi_input,
+            osd,
+            watch_presets
+        );
+
+        if changed.is_empty() && needs_restart.is_empty() {
+            println!("Config reloaded, nothing changed");
+            return;
+        }
+        if !changed.is_empty() {
+            println!("Config reloaded, applied {}", changed.join(", "));
+            self.osd_message(format!("Config reloaded: {}", changed.join(", ")));
+        }
+        if !needs_restart.is_empty() {
+            println!(
+                "Warning: restart to apply changes to {}",
+                needs_restart.join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use projectm::core::ProjectM;
+use sdl3::event::Event;
+
+use crate::app::audio::file::FileReader;
+use crate::app::audio::CHANNELS;
+use crate::app::clock::{Clock, ClockMode};
+use crate::app::headless::read_pixels;
+use crate::app::App;
+
+pub mod encoder;
+
+use encoder::{Encoder, FfmpegEncoder, Y4mEncoder};
+
+/// What to render offline and where to write it.
+pub struct RenderOptions {
+    /// Audio file to visualize.
+    pub audio_file: PathBuf,
+    /// Preset file or directory of presets to render.
+    pub preset: Option<PathBuf>,
+    /// Output file. `.y4m` writes raw video plus a `.wav` file, anything else is encoded by ffmpeg.
+    pub output: PathBuf,
+    pub frame_rate: u32,
+}
+
+impl App {
+    /// Render an audio file to a video file at a fixed frame rate.
+    ///
+    /// Frames are stepped on an unpaced clock, as fast as they can be drawn, and
+    /// each one is fed exactly the audio that plays during it. libprojectM still
+    /// animates presets and transitions from its own wall-clock timer, so motion
+    /// in the video follows rendering speed rather than video time and isn't
+    /// frame-exact.
+    pub fn render(&mut self, options: &RenderOptions) -> Result<(), String> {
+        self.apply_config(&self.config);
+        self.update_projectm_window_size();
+
+        match &options.preset {
+            Some(preset) if preset.is_file() => {
+                println!("Loading preset {}", preset.display());
+                // projectM expects a NUL-terminated path
+                self.pm
+                    .load_preset_file(&format!("{}\0", preset.display()), false);
+                // stay on this preset for the whole render
+                self.pm.set_preset_locked(true);
+            }
+            preset => {
+                if let Some(preset_dir) = preset {
+                    self.add_preset_path(preset_dir);
+                }
+                self.apply_playlist_options();
+                // start on the first preset rather than the idle preset
+                self.playlist_play_first();
+            }
+        }
+
+        let (width, height) = self.window.size_in_pixels();
+        let mut reader = FileReader::open(&options.audio_file)?;
+        let sample_rate = reader.sample_rate() as u64;
+        let frame_rate = options.frame_rate as u64;
+
+        let mut encoder: Box<dyn Encoder> =
+            match options.output.extension().and_then(|ext| ext.to_str()) {
+                Some("y4m") => Box::new(Y4mEncoder::create(
+                    &options.output,
+                    width,
+                    height,
+                    options.frame_rate,
+                    reader.sample_rate(),
+                )?),
+                _ => Box::new(FfmpegEncoder::spawn(
+                    &options.output,
+                    &options.audio_file,
+                    width,
+                    height,
+                    options.frame_rate,
+                )?),
+            };
+
+        println!(
+            "Rendering {} to {} at {}x{}, {} fps",
+            options.audio_file.display(),
+            options.output.display(),
+            width,
+            height,
+            frame_rate
+        );
+
+        // projectM takes at most this many samples at once, in whole stereo frames
+        let max_samples = ProjectM::pcm_get_max_samples() as usize;
+        let max_samples = max_samples - max_samples % CHANNELS as usize;
+
+        let mut event_pump = self.sdl_context.event_pump().unwrap();
+        let mut pending: Vec<f32> = Vec::new();
+        let mut end_of_audio = false;
+        self.clock = Clock::new(ClockMode::Unpaced, options.frame_rate);
+
+        loop {
+            let frame = self.clock.frame();
+
+            // Sample frames that play during this video frame
+            let first = frame * sample_rate / frame_rate;
+            let last = (frame + 1) * sample_rate / frame_rate;
+            let wanted = (last - first) as usize * CHANNELS as usize;
+
+            while pending.len() < wanted && !end_of_audio {
+                match reader.next_samples() {
+                    Some(samples) => pending.extend(samples),
+                    None => end_of_audio = true,
+                }
+            }
+            if pending.is_empty() {
+                break;
+            }
+
+            let samples: Vec<f32> = pending.drain(..wanted.min(pending.len())).collect();
+            for chunk in samples.chunks(max_samples) {
+                self.pm.pcm_add_float(chunk, CHANNELS);
+            }
+            encoder.write_samples(&samples)?;
+
+            self.pm.render_frame();
+            self.handle_preset_switches();
+            self.check_preset_duration();
+            encoder.write_frame(&read_pixels(width, height))?;
+            self.window.gl_swap_window();
+            self.clock.end_frame();
+
+            let rendered = self.clock.frame();
+            if rendered % (frame_rate * 10) == 0 {
+                println!("Rendered {} seconds", rendered / frame_rate);
+            }
+
+            // Stop early on Ctrl-C, keeping what was rendered so far
+            if event_pump
+                .poll_iter()
+                .any(|event| matches!(event, Event::Quit { .. }))
+            {
+                println!("Render interrupted");
+                break;
+            }
+        }
+
+        encoder.finish()?;
+        println!(
+            "Rendered {} frames to {}",
+            self.clock.frame(),
+            options.output.display()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Receives rendered frames and audio and writes them to an output file.
+pub trait Encoder {
+    /// Write one frame of top-down RGBA pixels.
+    fn write_frame(&mut self, pixels: &[u8]) -> Result<(), String>;
+
+    /// Write interleaved stereo samples belonging to the frames written so far.
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    /// Flush and close the output.
+    fn finish(self: Box<Self>) -> Result<(), String>;
+}
+
+/// Pipes raw frames into a spawned `ffmpeg` process, which muxes in the audio file.
+pub struct FfmpegEncoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl FfmpegEncoder {
+    pub fn spawn(
+        output: &Path,
+        audio_file: &Path,
+        width: u32,
+        height: u32,
+        frame_rate: u32,
+    ) -> Result<Self, String> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "warning", "-y"])
+            // video from stdin
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &frame_rate.to_string()])
+            .args(["-i", "-"])
+            // audio from the source file
+            .arg("-i")
+            .arg(audio_file)
+            .args(["-map", "0:v", "-map", "1:a"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .args(["-c:a", "aac", "-shortest"])
+            .arg(output)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not start ffmpeg: {}", e))?;
+
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin })
+    }
+}
+
+impl Encoder for FfmpegEncoder {
+    fn write_frame(&mut self, pixels: &[u8]) -> Result<(), String> {
+        self.stdin
+            .as_mut()
+            .ok_or("ffmpeg input is closed")?
+            .write_all(pixels)
+            .map_err(|e| format!("could not write frame to ffmpeg: {}", e))
+    }
+
+    fn write_samples(&mut self, _samples: &[f32]) -> Result<(), String> {
+        // ffmpeg reads the audio from the source file
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), String> {
+        // Closing stdin lets ffmpeg finish encoding
+        drop(self.stdin.take());
+
+        let status = self
+            .child
+            .wait()
+            .map_err(|e| format!("could not wait for ffmpeg: {}", e))?;
+        if !status.success() {
+            return Err(format!("ffmpeg failed: {}", status));
+        }
+        Ok(())
+    }
+}
+
+/// Writes frames to a YUV4MPEG2 (`.y4m`) file and audio to a `.wav` file next to it.
+pub struct Y4mEncoder {
+    video: BufWriter<File>,
+    audio: WavWriter,
+    width: u32,
+    height: u32,
+}
+
+impl Y4mEncoder {
+    pub fn create(
+        output: &Path,
+        width: u32,
+        height: u32,
+        frame_rate: u32,
+        sample_rate: u32,
+    ) -> Result<Self, String> {
+        let mut video = BufWriter::new(
+            File::create(output)
+                .map_err(|e| format!("could not create {}: {}", output.display(), e))?,
+        );
+        writeln!(
+            video,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            width, height, frame_rate
+        )
+        .map_err(|e| format!("could not write {}: {}", output.display(), e))?;
+
+        let audio = WavWriter::create(&output.with_extension("wav"), sample_rate)?;
+
+        Ok(Self {
+            video,
+            audio,
+            width,
+            height,
+        })
+    }
+}
+
+impl Encoder for Y4mEncoder {
+    fn write_frame(&mut self, pixels: &[u8]) -> Result<(), String> {
+        let plane_len = (self.width * self.height) as usize;
+        let mut planes = vec![0u8; plane_len * 3];
+        let (y_plane, chroma) = planes.split_at_mut(plane_len);
+        let (u_plane, v_plane) = chroma.split_at_mut(plane_len);
+
+        // BT.601 studio range
+        for (i, rgba) in pixels.chunks_exact(4).enumerate() {
+            let (r, g, b) = (rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+            y_plane[i] = (16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0) as u8;
+            u_plane[i] = (128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0) as u8;
+            v_plane[i] = (128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0) as u8;
+        }
+
+        self.video
+            .write_all(b"FRAME\n")
+            .and_then(|_| self.video.write_all(&planes))
+            .map_err(|e| format!("could not write frame: {}", e))
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.audio.write(samples)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), String> {
+        self.video
+            .flush()
+            .map_err(|e| format!("could not write video: {}", e))?;
+        self.audio.finish()
+    }
+}
+
+/// Minimal 16-bit stereo PCM WAV writer.
+struct WavWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path, sample_rate: u32) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            data_len: 0,
+        };
+
+        let channels: u16 = 2;
+        let block_align = channels * 2;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes()); // patched in finish()
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes()); // patched in finish()
+        writer.write_bytes(&header)?;
+
+        Ok(writer)
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        self.data_len += bytes.len() as u32;
+        self.write_bytes(&bytes)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(bytes)
+            .map_err(|e| format!("could not write {}: {}", self.path.display(), e))
+    }
+
+    /// Fill in the chunk sizes now that the data length is known.
+    fn finish(mut self) -> Result<(), String> {
+        let data_len = self.data_len;
+        let error = |e: std::io::Error| format!("could not write {}: {}", self.path.display(), e);
+
+        self.file.flush().map_err(error)?;
+        let file = self.file.get_mut();
+        file.seek(SeekFrom::Start(4)).map_err(error)?;
+        file.write_all(&(36 + data_len).to_le_bytes())
+            .map_err(error)?;
+        file.seek(SeekFrom::Start(40)).map_err(error)?;
+        file.write_all(&data_len.to_le_bytes()).map_err(error)
+    }
+}
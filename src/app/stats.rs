@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::{config, App};
+
+/// Switching away from a preset on request within this time counts as
+/// skipping it.
+const SKIP_TIME: Duration = Duration::from_secs(5);
+/// Time between saves while running, so little is lost on a crash.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What's known about how a preset went down.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetStats {
+    /// Times the preset was switched to.
+    pub shown: u32,
+    /// Total seconds on screen.
+    pub seconds: f64,
+    /// Times it was switched away from within a few seconds.
+    pub skipped: u32,
+    /// Times it failed to load.
+    pub failed: u32,
+}
+
+impl PresetStats {
+    /// Rating between 0 and 1: the share of showings that weren't skipped,
+    /// starting from 0.5 for presets never shown. Each failure to load halves
+    /// the rating.
+    pub fn rating(&self) -> f64 {
+        let kept = self.shown.saturating_sub(self.skipped) as f64;
+        (kept + 1.0) / (self.shown as f64 + 2.0) / 2f64.powi(self.failed as i32)
+    }
+}
+
+/// Play statistics per preset, kept in a JSON file.
+pub struct Stats {
+    /// File the statistics are kept in, or `None` to only keep them for this run.
+    path: Option<PathBuf>,
+    presets: HashMap<PathBuf, PresetStats>,
+    /// The preset on screen and when it was switched to.
+    current: Option<(PathBuf, Duration)>,
+    last_saved: Duration,
+    changed: bool,
+}
+
+impl Stats {
+    /// Load the statistics kept in `path`. A missing file has none.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, String> {
+        let presets = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| format!("invalid statistics {}: {}", path.display(), e))?
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            path,
+            presets,
+            current: None,
+            last_saved: Duration::ZERO,
+            changed: false,
+        })
+    }
+
+    /// Load the statistics from the data directory.
+    pub fn load_default() -> Result<Self, String> {
+        Self::load(config::data_dir().map(|dir| dir.join("stats.json")))
+    }
+
+    /// Statistics of `preset`, if it has any.
+    pub fn get(&self, preset: &Path) -> Option<&PresetStats> {
+        self.presets.get(preset)
+    }
+
+    /// Rating of `preset`, see [`PresetStats::rating`].
+    pub fn rating(&self, preset: &Path) -> f64 {
+        self.get(preset).cloned().unwrap_or_default().rating()
+    }
+
+    /// Record that `preset` was switched to at `now`, ending the time on screen
+    /// of the one before. `requested` is whether the user or a command asked
+    /// for the switch, rather than it happening on its own.
+    pub fn preset_started(&mut self, preset: Option<PathBuf>, now: Duration, requested: bool) {
+        self.preset_ended(now, requested);
+        if let Some(preset) = &preset {
+            self.presets.entry(preset.clone()).or_default().shown += 1;
+            self.changed = true;
+        }
+        self.current = preset.map(|preset| (preset, now));
+    }
+
+    /// Record the time on screen of the current preset up to `now`. Only
+    /// `requested` switches can count as skipping it: timed switches, hard cuts
+    /// and exiting don't say anything about the preset.
+    pub fn preset_ended(&mut self, now: Duration, requested: bool) {
+        if let Some((preset, started)) = self.current.take() {
+            let played = now.saturating_sub(started);
+            let stats = self.presets.entry(preset).or_default();
+            stats.seconds += played.as_secs_f64();
+            if requested && played < SKIP_TIME {
+                stats.skipped += 1;
+            }
+            self.changed = true;
+        }
+    }
+
+    pub fn preset_failed(&mut self, preset: &Path) {
+        self.presets.entry(preset.to_path_buf()).or_default().failed += 1;
+        self.changed = true;
+    }
+
+    /// Save the statistics if they changed and it's been a while since the
+    /// last save.
+    pub fn save_if_due(&mut self, now: Duration) -> Result<(), String> {
+        if self.changed && now.saturating_sub(self.last_saved) >= SAVE_INTERVAL {
+            self.last_saved = now;
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.presets).map_err(|e| e.to_string())?;
+        fs::write(path, contents + "\n")
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// All presets with statistics, best rated first, then most played.
+    pub fn ranked(&self) -> Vec<(&PathBuf, &PresetStats)> {
+        let mut ranked: Vec<_> = self.presets.iter().collect();
+        ranked.sort_by(|(a_path, a), (b_path, b)| {
+            b.rating()
+                .total_cmp(&a.rating())
+                .then(b.seconds.total_cmp(&a.seconds))
+                .then(a_path.cmp(b_path))
+        });
+        ranked
+    }
+}
+
+impl App {
+    /// End the current preset's time on screen and save the statistics. Call
+    /// before exiting.
+    pub fn save_stats(&mut self) {
+        self.stats.preset_ended(self.clock.now(), false);
+        if let Err(e) = self.stats.save() {
+            println!("Warning: could not save statistics: {}", e);
+        }
+    }
+}
+
+/// Print the `count` best and worst rated presets.
+pub fn print_stats(count: usize) -> Result<(), String> {
+    let stats = Stats::load_default()?;
+    let ranked = stats.ranked();
+    if ranked.is_empty() {
+        println!("No presets played yet");
+        return Ok(());
+    }
+
+    let print = |title: &str, presets: &[(&PathBuf, &PresetStats)]| {
+        println!("{}:", title);
+        println!(
+            "{:>6} {:>6} {:>10} {:>7} {:>6}  preset",
+            "rating", "shown", "time", "skipped", "failed"
+        );
+        for (path, preset) in presets {
+            println!(
+                "{:>6.2} {:>6} {:>10} {:>7} {:>6}  {}",
+                preset.rating(),
+                preset.shown,
+                format_seconds(preset.seconds),
+                preset.skipped,
+                preset.failed,
+                path.display()
+            );
+        }
+    };
+
+    if ranked.len() <= 2 * count {
+        print("Presets", &ranked);
+    } else {
+        print("Top presets", &ranked[..count]);
+        println!();
+        print("Bottom presets", &ranked[ranked.len() - count..]);
+    }
+    Ok(())
+}
+
+/// Format `seconds` as `h:mm:ss`.
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_rating() {
+        let rating = |shown, skipped, failed| {
+            PresetStats {
+                shown,
+                seconds: 0.0,
+                skipped,
+                failed,
+            }
+            .rating()
+        };
+        assert_eq!(rating(0, 0, 0), 0.5);
+        assert_eq!(rating(8, 0, 0), 0.9);
+        assert_eq!(rating(8, 8, 0), 0.1);
+        assert_eq!(rating(0, 0, 1), 0.25);
+    }
+
+    #[test]
+    fn test_record_stats() {
+        let temp = TempDir::new();
+        // the statistics create their directory when saving
+        let path = temp.path().join("data/stats.json");
+        let (a, b) = (
+            PathBuf::from("/presets/a.milk"),
+            PathBuf::from("/presets/b.milk"),
+        );
+
+        let mut stats = Stats::load(Some(path.clone())).unwrap();
+        stats.preset_started(Some(a.clone()), Duration::ZERO, false);
+        stats.preset_started(Some(b.clone()), Duration::from_secs(30), false);
+        stats.preset_started(Some(a.clone()), Duration::from_secs(32), true);
+        // a hard cut or exit soon after a switch isn't a skip
+        stats.preset_started(Some(b.clone()), Duration::from_secs(62), false);
+        stats.preset_ended(Duration::from_secs(63), false);
+        stats.preset_failed(&b);
+        stats.save().unwrap();
+
+        let stats = Stats::load(Some(path)).unwrap();
+        assert_eq!(
+            stats.get(&a),
+            Some(&PresetStats {
+                shown: 2,
+                seconds: 60.0,
+                skipped: 0,
+                failed: 0,
+            })
+        );
+        assert_eq!(
+            stats.get(&b),
+            Some(&PresetStats {
+                shown: 2,
+                seconds: 3.0,
+                skipped: 1,
+                failed: 1,
+            })
+        );
+        assert_eq!(stats.ranked()[0].0, &a);
+    }
+
+    #[test]
+    fn test_format_seconds() {
+        assert_eq!(format_seconds(59.9), "0:00:59");
+        assert_eq!(format_seconds(3723.0), "1:02:03");
+    }
+}
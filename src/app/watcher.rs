@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches files and directories for changes on a background thread.
+pub struct FileWatcher {
+    receiver: Receiver<notify::Result<Event>>,
+    watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new() -> Result<Self, String> {
+        let (sender, receiver) = channel();
+        let watcher = notify::recommended_watcher(sender).map_err(|e| e.to_string())?;
+        Ok(Self { receiver, watcher })
+    }
+
+    /// Watch `path`, and with `recursive` everything below it.
+    pub fn watch(&mut self, path: &Path, recursive: bool) -> Result<(), String> {
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        self.watcher
+            .watch(path, mode)
+            .map_err(|e| format!("could not watch {}: {}", path.display(), e))
+    }
+
+    /// Events received since the last call. Errors are reported and skipped.
+    pub fn poll(&mut self) -> Vec<Event> {
+        self.receiver
+            .try_iter()
+            .filter_map(|event| {
+                event
+                    .map_err(|e| println!("Warning: file watcher: {}", e))
+                    .ok()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use confique::{Config as ConfiqueConfig, FileFormat};
+
+use crate::app::audio::synth::SynthSpec;
+use crate::app::config::parse_mesh_size;
+use crate::app::playlist::{is_glob, PlaylistOrder};
+use crate::{
+    config_file_format, load_settings, load_settings_file, user_config_dir, Settings, Source,
+};
+
+/// Print the settings in effect as TOML, each with where its value came from.
+pub fn show(cli: &Settings, cli_sources: &BTreeMap<String, Source>) -> Result<(), String> {
+    let loaded = load_settings(cli, cli_sources)?;
+    if loaded.files.is_empty() {
+        println!("# No config file found");
+    }
+
+    // written out and read back so values print as they would be written,
+    // e.g. f32 values without rounding noise
+    let contents = toml::to_string(&loaded.settings).map_err(|e| e.to_string())?;
+    let values: toml::Table = toml::from_str(&contents).map_err(|e| e.to_string())?;
+    for field in Settings::META.fields {
+        if let Some(value) = values.get(field.name) {
+            let source = loaded
+                .sources
+                .get(field.name)
+                .cloned()
+                .unwrap_or(Source::Default);
+            println!("{} = {}  # {}", field.name, value, source);
+        }
+    }
+    Ok(())
+}
+
+/// Write a config file template with every setting documented and commented
+/// out to `path`, by default `config.toml` in the user's config directory.
+pub fn init(path: Option<PathBuf>, force: bool) -> Result<(), String> {
+    let path = match path {
+        Some(path) => path,
+        None => user_config_dir()
+            .ok_or("could not find the user's config directory")?
+            .join("config.toml"),
+    };
+    let template = match config_file_format(&path)? {
+        FileFormat::Toml => confique::toml::template::<Settings>(Default::default()),
+        FileFormat::Yaml => confique::yaml::template::<Settings>(Default::default()),
+        FileFormat::Json5 => confique::json5::template::<Settings>(Default::default()),
+    };
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        ));
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&path, template).map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    println!("Wrote config template to {}", path.display());
+    Ok(())
+}
+
+/// Load the config file at `path` and report problems with its settings.
+/// Fails if it doesn't load or has any.
+pub fn check(path: &Path) -> Result<(), String> {
+    let settings = load_settings_file(Some(path.to_path_buf()))?;
+    let errors = check_settings(&settings);
+    if errors.is_empty() {
+        println!("{} is valid", path.display());
+        return Ok(());
+    }
+
+    for error in &errors {
+        println!("Error: {}", error);
+    }
+    Err(format!(
+        "{} has {} invalid settings",
+        path.display(),
+        errors.len()
+    ))
+}
+
+/// Problems with `settings`: paths that don't exist, values out of range and
+/// values that don't parse.
+fn check_settings(settings: &Settings) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for path in settings.preset_path.iter().flatten() {
+        if is_glob(path) {
+            let pattern = path.to_string_lossy();
+            if let Err(e) = glob::Pattern::new(&pattern) {
+                errors.push(format!("preset_path: invalid pattern {}: {}", pattern, e));
+            }
+        } else if !path.exists() {
+            errors.push(format!("preset_path: {} does not exist", path.display()));
+        }
+    }
+    for path in settings.texture_path.iter().flatten() {
+        if !path.is_dir() {
+            errors.push(format!(
+                "texture_path: {} is not a directory",
+                path.display()
+            ));
+        }
+    }
+    if let Some(path) = &settings.audio_file {
+        if !path.is_file() {
+            errors.push(format!("audio_file: {} does not exist", path.display()));
+        }
+    }
+
+    let mut check_range = |key: &str, value: Option<f64>, min: f64, max: f64| match value {
+        Some(value) if !(min..=max).contains(&value) => errors.push(if max == f64::MAX {
+            format!(
+                "{}: {} is out of range, expected at least {}",
+                key, value, min
+            )
+        } else {
+            format!(
+                "{}: {} is out of range, expected {} to {}",
+                key, value, min, max
+            )
+        }),
+        _ => {}
+    };
+    check_range(
+        "frame_rate",
+        settings.frame_rate.map(f64::from),
+        1.0,
+        1000.0,
+    );
+    check_range("width", settings.width.map(f64::from), 1.0, 16384.0);
+    check_range("height", settings.height.map(f64::from), 1.0, 16384.0);
+    check_range("frames", settings.frames.map(f64::from), 1.0, f64::MAX);
+    check_range(
+        "beat_sensitivity",
+        settings.beat_sensitivity.map(f64::from),
+        0.0,
+        5.0,
+    );
+    check_range(
+        "hard_cut_sensitivity",
+        settings.hard_cut_sensitivity.map(f64::from),
+        0.0,
+        5.0,
+    );
+    check_range("preset_duration", settings.preset_duration, 1.0, f64::MAX);
+    check_range(
+        "hard_cut_duration",
+        settings.hard_cut_duration,
+        0.0,
+        f64::MAX,
+    );
+    check_range(
+        "soft_cut_duration",
+        settings.soft_cut_duration,
+        0.0,
+        f64::MAX,
+    );
+    check_range("osd_duration", settings.osd_duration, 0.0, f64::MAX);
+    check_range(
+        "easter_egg",
+        settings.easter_egg.map(f64::from),
+        0.0,
+        f64::MAX,
+    );
+
+    if let Some(Err(e)) = settings.mesh_size.as_deref().map(parse_mesh_size) {
+        errors.push(format!("mesh_size: {}", e));
+    }
+    if let Some(Err(e)) = settings.playlist_order.as_deref().map(PlaylistOrder::parse) {
+        errors.push(format!("playlist_order: {}", e));
+    }
+    match settings.audio_source.as_deref() {
+        None | Some("capture") | Some("dummy") | Some("synth") => {}
+        Some(other) => match other.strip_prefix("synth:").map(SynthSpec::parse) {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => errors.push(format!("audio_source: {}", e)),
+            None => errors.push(format!("audio_source: unknown audio source '{}'", other)),
+        },
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_settings() {
+        let valid = Settings {
+            preset_path: Some(vec![
+                PathBuf::from("test-data/presets"),
+                PathBuf::from("test-data/presets/*.milk"),
+            ]),
+            beat_sensitivity: Some(1.5),
+            mesh_size: Some("48x32".to_string()),
+            audio_source: Some("synth:kick,bpm=128".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(check_settings(&valid), Vec::<String>::new());
+
+        let invalid = Settings {
+            preset_path: Some(vec![PathBuf::from("test-data/missing")]),
+            frame_rate: Some(0),
+            beat_sensitivity: Some(-1.0),
+            preset_duration: Some(f64::NAN),
+            mesh_size: Some("48".to_string()),
+            playlist_order: Some("size".to_string()),
+            audio_source: Some("microphone".to_string()),
+            ..Default::default()
+        };
+        let errors = check_settings(&invalid);
+        let keys: Vec<&str> = errors
+            .iter()
+            .map(|error| error.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "preset_path",
+                "frame_rate",
+                "beat_sensitivity",
+                "preset_duration",
+                "mesh_size",
+                "playlist_order",
+                "audio_source"
+            ]
+        );
+    }
+}
@@ -1,69 +1,324 @@
 mod app;
-mod dummy_audio;
-use std::path::PathBuf;
+mod config_command;
+#[cfg(test)]
+mod test_util;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
-use crate::app::config::Config;
-use clap::Parser;
-use confique::Config as ConfiqueConfig;
+use crate::app::config::{default_resource_paths, Config};
+use crate::app::render::RenderOptions;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use confique::{Config as ConfiqueConfig, File, FileFormat};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-// User specified configuration options.
-//
-// Defines CLI, env, and config file parameters.
-#[derive(Parser, ConfiqueConfig, Clone, Debug)]
+/// Separator for lists of paths in environment variables, as in `PATH`.
+const PATH_LIST_SEPARATOR: char = if cfg!(windows) { ';' } else { ':' };
+
+/// Config files looked for in each config directory, in order.
+const CONFIG_FILE_NAMES: &[&str] = &[
+    "config.toml",
+    "config.yaml",
+    "config.yml",
+    "config.json5",
+    "config.json",
+];
+
+/// Deserialize a list of paths given either as a single path or as an array.
+fn deserialize_path_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PathList {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match PathList::deserialize(deserializer)? {
+        PathList::One(path) => vec![path],
+        PathList::Many(paths) => paths,
+    })
+}
+
+// Command line interface: settings for the visualizer plus subcommands.
+#[derive(Parser, Debug)]
 #[command(version)]
 /// ProjectM: the milkdrop-compatible music visualizer.
 ///
 /// Need help? Join discord: https://discord.gg/uSSggaMBrv
+struct Cli {
+    #[command(flatten)]
+    settings: Settings,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render an audio file to a video file offline
+    Render {
+        /// Audio file (WAV, FLAC, OGG, MP3) to visualize
+        audio_file: PathBuf,
+
+        #[arg(long)]
+        /// Preset file or preset directory to render (defaults to the preset path)
+        preset: Option<PathBuf>,
+
+        #[arg(short, long, default_value = "render.mp4")]
+        /// Output file; .y4m writes raw video plus a .wav file, anything else is encoded with ffmpeg
+        output: PathBuf,
+
+        #[arg(long, default_value = "1920", value_parser = clap::value_parser!(u32).range(1..))]
+        /// Output width
+        width: u32,
+
+        #[arg(long, default_value = "1080", value_parser = clap::value_parser!(u32).range(1..))]
+        /// Output height
+        height: u32,
+
+        #[arg(long, default_value = "60", value_parser = clap::value_parser!(u32).range(1..))]
+        /// Output frame rate
+        fps: u32,
+    },
+    /// List the best and worst rated presets from the play statistics
+    Stats {
+        #[arg(short = 'n', long, default_value = "10")]
+        /// Number of presets to list from each end
+        count: usize,
+    },
+    /// Show, create and check config files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the settings in effect, merged from the config files, environment and command line, with where each came from
+    Show,
+    /// Write a config file template with every setting documented and commented out
+    Init {
+        /// File to write, .toml, .yaml or .json5 (defaults to config.toml in the user's config directory)
+        path: Option<PathBuf>,
+
+        #[arg(short, long)]
+        /// Overwrite the file if it exists
+        force: bool,
+    },
+    /// Check a config file for errors, such as missing paths and values out of range
+    Check {
+        /// Config file to check
+        file: PathBuf,
+    },
+}
+
+// User specified configuration options.
+//
+// Defines CLI, env, and config file parameters.
+#[derive(Parser, ConfiqueConfig, Serialize, Clone, Debug, Default)]
 struct Settings {
     #[arg(short, long = "config")]
     /// Path to a config file
     config_path: Option<PathBuf>,
 
     #[arg(short, long)]
-    #[arg(default_value = "60")]
     #[arg(env = "PM_FRAME_RATE")]
-    /// Frame rate to render at.
+    /// Frame rate to render at (default 60)
     frame_rate: Option<u32>,
 
-    #[arg(short, long)]
+    #[arg(short, long, value_delimiter = PATH_LIST_SEPARATOR)]
     #[arg(env = "PM_PRESET_PATH")]
-    /// Path to preset directory
-    preset_path: Option<PathBuf>,
+    #[config(deserialize_with = deserialize_path_list)]
+    /// Preset directories, preset files or glob patterns; repeat or separate with ':'
+    preset_path: Option<Vec<PathBuf>>,
 
-    #[arg(short, long)]
+    #[arg(short, long, value_delimiter = PATH_LIST_SEPARATOR)]
     #[arg(env = "PM_TEXTURE_PATH")]
-    /// Path to texture directory
-    texture_path: Option<PathBuf>,
+    #[config(deserialize_with = deserialize_path_list)]
+    /// Texture directories; repeat or separate with ':'
+    texture_path: Option<Vec<PathBuf>>,
 
     #[arg(short, long)]
-    #[arg(default_value = "1.0")]
     #[arg(env = "PM_BEAT_SENSITIVITY")]
-    /// Sensitivity of the beat detection
+    /// Sensitivity of the beat detection (default 1.0)
     beat_sensitivity: Option<f32>,
 
     #[arg(short = 'd', long)]
-    #[arg(default_value = "10")]
     #[arg(env = "PM_PRESET_DURATION")]
-    /// Duration (seconds) each preset will play
+    /// Duration (seconds) each preset will play (default 10)
     preset_duration: Option<f64>,
-    // TODO: Add option for specifying audio device
-    // #[arg(short, long)]
-    // #[arg(env = "PM_AUDIO_INPUT")]
-    // /// Audio input device (name or index)
-    // audio_input: Option<String>,
-}
 
-impl Default for Settings {
-    fn default() -> Self {
-        Settings {
-            config_path: None,
-            frame_rate: None,
-            preset_path: None,
-            texture_path: None,
-            beat_sensitivity: None,
-            preset_duration: None,
-        }
-    }
+    #[arg(short = 's', long)]
+    #[arg(env = "PM_AUDIO_SOURCE")]
+    /// Audio source: capture, or synth:<sweep|white|pink|kick|random>[,bpm=N][,period=SECS][,seed=N]
+    audio_source: Option<String>,
+
+    #[arg(short, long)]
+    #[arg(env = "PM_AUDIO_INPUT")]
+    /// Audio input device (name, name pattern, or index)
+    audio_input: Option<String>,
+
+    #[arg(long)]
+    #[arg(env = "PM_AUDIO_FILE")]
+    /// Audio file (WAV, FLAC, OGG, MP3) to play instead of capturing audio
+    audio_file: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(env = "PM_WIDTH")]
+    /// Window width (defaults to the display width)
+    width: Option<u32>,
+
+    #[arg(long)]
+    #[arg(env = "PM_HEIGHT")]
+    /// Window height (defaults to the display height)
+    height: Option<u32>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_HEADLESS")]
+    /// Render offscreen and write PNG frames instead of opening a window
+    headless: Option<bool>,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    #[arg(env = "PM_FRAMES")]
+    /// Number of frames to render in headless mode (default 300)
+    frames: Option<u32>,
+
+    #[arg(short, long)]
+    #[arg(env = "PM_OUTPUT_DIR")]
+    /// Directory to write headless frames to (default "frames")
+    output_dir: Option<PathBuf>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_FIXED_STEP")]
+    /// Feed exactly one frame interval of audio per frame and seed the preset order, for repeatable audio and presets
+    fixed_step: Option<bool>,
+
+    #[arg(long)]
+    #[arg(env = "PM_SEED")]
+    /// Seed for random preset selection (default 0 with --fixed-step)
+    seed: Option<u64>,
+
+    #[arg(long)]
+    #[arg(env = "PM_IPC_SOCKET")]
+    /// Unix socket path to accept JSON commands on
+    ipc_socket: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(env = "PM_OSC_PORT")]
+    /// UDP port to listen on for OSC messages
+    osc_port: Option<u16>,
+
+    #[arg(long)]
+    #[arg(env = "PM_OSC_BIND")]
+    /// Address to listen on for OSC messages, e.g. 0.0.0.0 for all interfaces (default 127.0.0.1)
+    osc_bind: Option<IpAddr>,
+
+    #[arg(skip)]
+    /// OSC addresses mapped to actions, e.g. "/1/push1" = "next" (config file only)
+    osc_addresses: Option<HashMap<String, String>>,
+
+    #[arg(long)]
+    #[arg(env = "PM_MIDI_INPUT")]
+    /// MIDI input port (name, name pattern, or index), or "virtual" to create a port
+    midi_input: Option<String>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_MIDI_LEARN")]
+    /// Print incoming MIDI messages, to help set up MIDI mappings
+    midi_learn: Option<bool>,
+
+    #[arg(skip)]
+    /// MIDI notes mapped to actions, e.g. "60" = "next" (config file only)
+    midi_notes: Option<HashMap<String, String>>,
+
+    #[arg(skip)]
+    /// MIDI controllers mapped to settings, e.g. "7" = "beat_sensitivity 0 5" (config file only)
+    midi_controllers: Option<HashMap<String, String>>,
+
+    #[arg(skip)]
+    /// Key chords mapped to actions, e.g. "Ctrl+N" = "next_preset" (config file only)
+    keybindings: Option<HashMap<String, String>>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_OSD")]
+    /// Show messages on screen, e.g. on preset switches (default: true unless headless)
+    osd: Option<bool>,
+
+    #[arg(long)]
+    #[arg(env = "PM_OSD_DURATION")]
+    /// Seconds on-screen messages stay visible
+    osd_duration: Option<f64>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_HARD_CUT_ENABLED")]
+    /// Allow switching presets without a transition on strong beats
+    hard_cut_enabled: Option<bool>,
+
+    #[arg(long)]
+    #[arg(env = "PM_HARD_CUT_DURATION")]
+    /// Minimum seconds a preset plays before a hard cut
+    hard_cut_duration: Option<f64>,
+
+    #[arg(long)]
+    #[arg(env = "PM_HARD_CUT_SENSITIVITY")]
+    /// Beat intensity needed for a hard cut
+    hard_cut_sensitivity: Option<f32>,
+
+    #[arg(long)]
+    #[arg(env = "PM_SOFT_CUT_DURATION")]
+    /// Seconds a transition between presets takes
+    soft_cut_duration: Option<f64>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_ASPECT_CORRECTION")]
+    /// Correct presets' shapes and waveforms for the window's aspect ratio
+    aspect_correction: Option<bool>,
+
+    #[arg(long)]
+    #[arg(env = "PM_MESH_SIZE")]
+    /// Per-pixel mesh size as WIDTHxHEIGHT, e.g. 48x32; larger is smoother but slower
+    mesh_size: Option<String>,
+
+    #[arg(long)]
+    #[arg(env = "PM_EASTER_EGG")]
+    /// Spread of randomized preset durations around the preset duration
+    easter_egg: Option<f32>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_SHUFFLE")]
+    /// Play presets in random order
+    shuffle: Option<bool>,
+
+    #[arg(long)]
+    #[arg(env = "PM_PLAYLIST_ORDER")]
+    /// Playlist order: name, path, mtime, or random (seeded by --seed)
+    playlist_order: Option<String>,
+
+    #[arg(long)]
+    #[arg(env = "PM_PLAYLIST")]
+    /// Playlist file (.m3u or .json) to load presets from and save the playlist to
+    playlist: Option<PathBuf>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_FAVORITES_ONLY")]
+    /// Only play favorite presets
+    favorites_only: Option<bool>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_WEIGHTED_RANDOM")]
+    /// Favor presets that were rarely skipped when picking a random preset
+    weighted_random: Option<bool>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    #[arg(env = "PM_WATCH_PRESETS")]
+    /// Reload presets when their files change, and add and remove presets as files appear and disappear
+    watch_presets: Option<bool>,
 }
 
 impl Settings {
@@ -87,6 +342,108 @@ impl Settings {
         if let Some(preset_duration) = other.preset_duration {
             self.preset_duration = Some(preset_duration);
         }
+        if let Some(audio_source) = &other.audio_source {
+            self.audio_source = Some(audio_source.clone());
+        }
+        if let Some(audio_input) = &other.audio_input {
+            self.audio_input = Some(audio_input.clone());
+        }
+        if let Some(audio_file) = &other.audio_file {
+            self.audio_file = Some(audio_file.clone());
+        }
+        if let Some(width) = other.width {
+            self.width = Some(width);
+        }
+        if let Some(height) = other.height {
+            self.height = Some(height);
+        }
+        if let Some(headless) = other.headless {
+            self.headless = Some(headless);
+        }
+        if let Some(frames) = other.frames {
+            self.frames = Some(frames);
+        }
+        if let Some(output_dir) = &other.output_dir {
+            self.output_dir = Some(output_dir.clone());
+        }
+        if let Some(fixed_step) = other.fixed_step {
+            self.fixed_step = Some(fixed_step);
+        }
+        if let Some(seed) = other.seed {
+            self.seed = Some(seed);
+        }
+        if let Some(ipc_socket) = &other.ipc_socket {
+            self.ipc_socket = Some(ipc_socket.clone());
+        }
+        if let Some(osc_port) = other.osc_port {
+            self.osc_port = Some(osc_port);
+        }
+        if let Some(osc_bind) = other.osc_bind {
+            self.osc_bind = Some(osc_bind);
+        }
+        if let Some(osc_addresses) = &other.osc_addresses {
+            self.osc_addresses = Some(osc_addresses.clone());
+        }
+        if let Some(midi_input) = &other.midi_input {
+            self.midi_input = Some(midi_input.clone());
+        }
+        if let Some(midi_learn) = other.midi_learn {
+            self.midi_learn = Some(midi_learn);
+        }
+        if let Some(midi_notes) = &other.midi_notes {
+            self.midi_notes = Some(midi_notes.clone());
+        }
+        if let Some(midi_controllers) = &other.midi_controllers {
+            self.midi_controllers = Some(midi_controllers.clone());
+        }
+        if let Some(keybindings) = &other.keybindings {
+            self.keybindings = Some(keybindings.clone());
+        }
+        if let Some(osd) = other.osd {
+            self.osd = Some(osd);
+        }
+        if let Some(osd_duration) = other.osd_duration {
+            self.osd_duration = Some(osd_duration);
+        }
+        if let Some(hard_cut_enabled) = other.hard_cut_enabled {
+            self.hard_cut_enabled = Some(hard_cut_enabled);
+        }
+        if let Some(hard_cut_duration) = other.hard_cut_duration {
+            self.hard_cut_duration = Some(hard_cut_duration);
+        }
+        if let Some(hard_cut_sensitivity) = other.hard_cut_sensitivity {
+            self.hard_cut_sensitivity = Some(hard_cut_sensitivity);
+        }
+        if let Some(soft_cut_duration) = other.soft_cut_duration {
+            self.soft_cut_duration = Some(soft_cut_duration);
+        }
+        if let Some(aspect_correction) = other.aspect_correction {
+            self.aspect_correction = Some(aspect_correction);
+        }
+        if let Some(mesh_size) = &other.mesh_size {
+            self.mesh_size = Some(mesh_size.clone());
+        }
+        if let Some(easter_egg) = other.easter_egg {
+            self.easter_egg = Some(easter_egg);
+        }
+        if let Some(shuffle) = other.shuffle {
+            self.shuffle = Some(shuffle);
+        }
+        if let Some(playlist_order) = &other.playlist_order {
+            self.playlist_order = Some(playlist_order.clone());
+        }
+        if let Some(playlist) = &other.playlist {
+            self.playlist = Some(playlist.clone());
+        }
+        if let Some(favorites_only) = other.favorites_only {
+            self.favorites_only = Some(favorites_only);
+        }
+        if let Some(weighted_random) = other.weighted_random {
+            self.weighted_random = Some(weighted_random);
+        }
+        if let Some(watch_presets) = other.watch_presets {
+            self.watch_presets = Some(watch_presets);
+        }
     }
 }
 
@@ -98,21 +455,34 @@ fn load_settings_file(path: Option<PathBuf>) -> Result<Settings, String> {
             return Err(format!("config path invalid: {}", path.display()));
         }
         // ensure extention is valid
-        match path.extension().and_then(|ext| ext.to_str()) {
-            Some("toml") | Some("json") | Some("yaml)") => {}
-            _ => {
-                return Err(format!(
-                    "invalid config file extension: {:?}",
-                    path.extension()
-                ))
-            }
-        }
+        let format = config_file_format(&path)?;
 
         println!("Loading config from: {}", path.display());
 
+        // Warn about settings we don't know, such as misspelled ones, which would be ignored
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        for (key, line) in unknown_keys(&contents, &format)
+            .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?
+        {
+            match line {
+                Some(line) => println!(
+                    "Warning: {}:{}: unknown setting {}",
+                    path.display(),
+                    line,
+                    key
+                ),
+                None => println!("Warning: {}: unknown setting {}", path.display(), key),
+            }
+        }
+
         // Load setting from file
+        let layer = File::with_format(&path, format)
+            .required()
+            .load()
+            .map_err(|e| e.to_string())?;
         let settings = Settings::builder()
-            .file(path)
+            .preloaded(layer)
             .load()
             .map_err(|e| e.to_string())?;
 
@@ -120,66 +490,357 @@ fn load_settings_file(path: Option<PathBuf>) -> Result<Settings, String> {
     }
 
     // No path, return empty settings
-    return Ok(Settings {
-        config_path: None,
-        frame_rate: None,
-        preset_path: None,
-        texture_path: None,
-        beat_sensitivity: None,
-        preset_duration: None,
-    });
+    Ok(Settings::default())
 }
 
-fn load_settings() -> Result<Settings, String> {
-    // Load CLI flags and env vars
-    let cli = Settings::parse();
+/// Format of the config file at `path`, by its extension, ignoring case.
+fn config_file_format(path: &Path) -> Result<FileFormat, String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| FileFormat::from_extension(ext.to_ascii_lowercase()))
+        .ok_or_else(|| {
+            format!(
+                "invalid config file extension: {} (expected .toml, .yaml, .yml, .json5 or .json)",
+                path.display()
+            )
+        })
+}
 
-    // Load file
-    let mut settings = load_settings_file(cli.config_path.clone())?;
+/// Top-level keys in the config file `contents` that aren't settings, with the
+/// line each is on, if found.
+fn unknown_keys(
+    contents: &str,
+    format: &FileFormat,
+) -> Result<Vec<(String, Option<usize>)>, String> {
+    let values: Value = match format {
+        FileFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string())?,
+        FileFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string())?,
+        FileFormat::Json5 => json5::from_str(contents).map_err(|e| e.to_string())?,
+    };
+    let keys = match values {
+        Value::Object(values) => values.into_iter().map(|(key, _)| key).collect(),
+        // an empty YAML file
+        Value::Null => Vec::new(),
+        _ => return Err("expected a table of settings".to_string()),
+    };
 
-    // Override file with CLI/env vars/defaults
-    settings.apply(&cli);
+    let known: Vec<&str> = Settings::META.fields.iter().map(|f| f.name).collect();
+    Ok(keys
+        .into_iter()
+        .filter(|key| !known.contains(&key.as_str()))
+        .map(|key| {
+            let line = key_line(contents, &key);
+            (key, line)
+        })
+        .collect())
+}
 
-    return Ok(settings);
+/// Line number, from 1, of the first line that looks like it sets `key`: the
+/// key, bare or quoted, followed by `=` or `:`.
+fn key_line(contents: &str, key: &str) -> Option<usize> {
+    let sets_key = |line: &str| {
+        line.match_indices(key).any(|(start, _)| {
+            let before = line[..start].chars().next_back();
+            let after = line[start + key.len()..]
+                .trim_start_matches(['"', '\''])
+                .trim_start();
+            !matches!(before, Some(c) if c.is_alphanumeric() || c == '_' || c == '-')
+                && (after.starts_with('=') || after.starts_with(':'))
+        })
+    };
+    contents.lines().position(sets_key).map(|index| index + 1)
 }
 
-fn main() -> Result<(), String> {
-    let settings = load_settings()?;
+/// Where the value of a setting came from.
+#[derive(Clone, Debug, PartialEq)]
+enum Source {
+    Default,
+    File(PathBuf),
+    Environment,
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "config file {}", path.display()),
+            Source::Environment => write!(f, "environment"),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Settings merged from all sources.
+struct LoadedSettings {
+    settings: Settings,
+    /// Config files read, lowest priority first.
+    files: Vec<PathBuf>,
+    /// Source of each setting that has a value.
+    sources: BTreeMap<String, Source>,
+}
+
+/// Config files to read when none is given, lowest priority first: the
+/// system-wide one, then the user's in `$XDG_CONFIG_HOME/projectm`.
+fn default_config_files() -> Vec<PathBuf> {
+    let mut config_dirs = Vec::new();
+    #[cfg(unix)]
+    config_dirs.push(PathBuf::from("/etc/projectm"));
+    config_dirs.extend(user_config_dir());
+    config_dirs
+        .iter()
+        .filter_map(|dir| find_config_file(dir))
+        .collect()
+}
+
+/// The user's config directory: `projectm` in e.g. `$XDG_CONFIG_HOME` on Linux.
+fn user_config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("projectm"))
+}
+
+/// The first of the config files looked for that exists in `dir`.
+fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Record `source` for each setting with a value in `settings`.
+fn record_sources(
+    sources: &mut BTreeMap<String, Source>,
+    settings: &Settings,
+    source: impl Fn(&str) -> Source,
+) {
+    if let Ok(Value::Object(values)) = serde_json::to_value(settings) {
+        for (key, value) in values {
+            if !value.is_null() {
+                let value_source = source(&key);
+                sources.insert(key, value_source);
+            }
+        }
+    }
+}
+
+/// Sources of the values in the settings parsed from the command line, which
+/// also reads environment variables.
+fn cli_sources(matches: &ArgMatches, cli: &Settings) -> BTreeMap<String, Source> {
+    let mut sources = BTreeMap::new();
+    record_sources(&mut sources, cli, |key| match matches.value_source(key) {
+        Some(ValueSource::EnvVariable) => Source::Environment,
+        Some(ValueSource::DefaultValue) => Source::Default,
+        _ => Source::CommandLine,
+    });
+    sources
+}
+
+/// Load the config file given on the command line, or else those found in
+/// the default locations, override them with `cli`, and fall back to defaults
+/// such as the presets and textures installed with projectM.
+fn load_settings(
+    cli: &Settings,
+    cli_sources: &BTreeMap<String, Source>,
+) -> Result<LoadedSettings, String> {
+    let files = match &cli.config_path {
+        Some(path) => vec![path.clone()],
+        None => default_config_files(),
+    };
+
+    // Load files
+    let mut settings = load_settings_file(None)?;
+    let mut sources = BTreeMap::new();
+    for file in &files {
+        let file_settings = load_settings_file(Some(file.clone()))?;
+        record_sources(&mut sources, &file_settings, |_| Source::File(file.clone()));
+        settings.apply(&file_settings);
+    }
 
-    let app_config = Config {
+    // Override files with CLI/env vars
+    record_sources(&mut sources, cli, |key| {
+        cli_sources.get(key).cloned().unwrap_or(Source::CommandLine)
+    });
+    settings.apply(cli);
+
+    // Fill in defaults for settings no source set
+    macro_rules! default {
+        ($field:ident, $value:expr) => {
+            if settings.$field.is_none() {
+                settings.$field = $value;
+                if settings.$field.is_some() {
+                    sources.insert(stringify!($field).to_string(), Source::Default);
+                }
+            }
+        };
+    }
+    default!(frame_rate, Some(60));
+    default!(beat_sensitivity, Some(1.0));
+    default!(preset_duration, Some(10.0));
+    default!(
+        preset_path,
+        Some(default_resource_paths("presets")).filter(|paths| !paths.is_empty())
+    );
+    default!(
+        texture_path,
+        Some(default_resource_paths("textures")).filter(|paths| !paths.is_empty())
+    );
+
+    Ok(LoadedSettings {
+        settings,
+        files,
+        sources,
+    })
+}
+
+/// The application configuration from the merged settings.
+fn config_from_settings(settings: Settings) -> Config {
+    Config {
         frame_rate: settings.frame_rate,
         preset_path: settings.preset_path,
         texture_path: settings.texture_path,
         beat_sensitivity: settings.beat_sensitivity,
         preset_duration: settings.preset_duration,
-    };
+        audio_source: settings.audio_source,
+        audio_input: settings.audio_input,
+        audio_file: settings.audio_file,
+        width: settings.width,
+        height: settings.height,
+        headless: settings.headless,
+        frames: settings.frames,
+        output_dir: settings.output_dir,
+        fixed_step: settings.fixed_step,
+        seed: settings.seed,
+        ipc_socket: settings.ipc_socket,
+        osc_port: settings.osc_port,
+        osc_bind: settings.osc_bind,
+        osc_addresses: settings.osc_addresses,
+        midi_input: settings.midi_input,
+        midi_learn: settings.midi_learn,
+        midi_notes: settings.midi_notes,
+        midi_controllers: settings.midi_controllers,
+        keybindings: settings.keybindings,
+        osd: settings.osd,
+        osd_duration: settings.osd_duration,
+        hard_cut_enabled: settings.hard_cut_enabled,
+        hard_cut_duration: settings.hard_cut_duration,
+        hard_cut_sensitivity: settings.hard_cut_sensitivity,
+        soft_cut_duration: settings.soft_cut_duration,
+        aspect_correction: settings.aspect_correction,
+        mesh_size: settings.mesh_size,
+        easter_egg: settings.easter_egg,
+        shuffle: settings.shuffle,
+        playlist_order: settings.playlist_order,
+        playlist: settings.playlist,
+        favorites_only: settings.favorites_only,
+        weighted_random: settings.weighted_random,
+        watch_presets: settings.watch_presets,
+    }
+}
+
+fn main() -> Result<(), String> {
+    // Load CLI flags and env vars
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let cli_sources = cli_sources(&matches, &cli.settings);
+
+    // config commands report on the config files rather than failing to load them
+    if let Some(Command::Config { command }) = &cli.command {
+        return match command {
+            ConfigCommand::Show => config_command::show(&cli.settings, &cli_sources),
+            ConfigCommand::Init { path, force } => config_command::init(path.clone(), *force),
+            ConfigCommand::Check { file } => config_command::check(file),
+        };
+    }
+
+    let loaded = load_settings(&cli.settings, &cli_sources)?;
+
+    // the highest priority config file is watched for changes
+    let config_path = loaded.files.last().cloned();
+    let mut app_config = config_from_settings(loaded.settings.clone());
+
+    match cli.command {
+        Some(Command::Render {
+            audio_file,
+            preset,
+            output,
+            width,
+            height,
+            fps,
+        }) => {
+            // Render offscreen at the requested size and frame rate
+            app_config.headless = Some(true);
+            app_config.width = Some(width);
+            app_config.height = Some(height);
+            app_config.frame_rate = Some(fps);
+            app_config.fixed_step = Some(true);
+
+            let mut app = app::App::new(app_config);
+            app.render(&RenderOptions {
+                audio_file,
+                preset,
+                output,
+                frame_rate: fps,
+            })?;
+        }
+        Some(Command::Stats { count }) => app::stats::print_stats(count)?,
+        Some(Command::Config { .. }) => unreachable!("handled before loading settings"),
+        None => {
+            println!("Settings from:");
+            for (key, source) in &loaded.sources {
+                println!("  {}: {}", key, source);
+            }
 
-    // Initialize the application
-    let mut app = app::App::new(app_config);
-    app.init();
-    app.main_loop();
+            // Initialize the application
+            let mut app = app::App::new(app_config);
+            app.init();
+
+            // apply changes to the config file while running
+            if let Some(config_path) = config_path {
+                let cli_settings = cli.settings.clone();
+                app.watch_config(
+                    &config_path,
+                    Box::new(move || {
+                        load_settings(&cli_settings, &cli_sources)
+                            .map(|loaded| config_from_settings(loaded.settings))
+                    }),
+                );
+            }
+            app.main_loop();
+        }
+    }
 
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Settings;
-    use clap::Parser;
-    use confique::Config;
+    use crate::test_util::TempDir;
+    use crate::{
+        cli_sources, config_file_format, deserialize_path_list, find_config_file, load_settings,
+        load_settings_file, unknown_keys, Cli, Settings, Source, PATH_LIST_SEPARATOR,
+    };
+    use clap::{CommandFactory, FromArgMatches, Parser};
+    use confique::{Config, FileFormat};
+    use serde::Deserialize;
+    use std::fs;
+    use std::path::{Path, PathBuf};
 
     fn assert_settings(s: Settings) {
         assert_eq!(s.frame_rate, Some(60));
         assert_eq!(
-            s.preset_path.as_ref().map(|p| p.to_str().unwrap()),
-            Some("/home/user/.local/share/projectm/presets")
+            s.preset_path,
+            Some(vec![PathBuf::from(
+                "/home/user/.local/share/projectm/presets"
+            )])
         );
         assert_eq!(
-            s.texture_path.as_ref().map(|p| p.to_str().unwrap()),
-            Some("/home/user/.local/share/projectm/textures")
+            s.texture_path,
+            Some(vec![PathBuf::from(
+                "/home/user/.local/share/projectm/textures"
+            )])
         );
         assert_eq!(s.beat_sensitivity, Some(1.0));
         assert_eq!(s.preset_duration, Some(10.0));
+        assert_eq!(s.audio_input.as_deref(), Some("default"));
     }
 
     #[test]
@@ -192,6 +853,78 @@ mod tests {
         assert_settings(res);
     }
 
+    #[test]
+    fn test_load_yaml() {
+        let res = Settings::builder()
+            .file("test-data/config.yaml")
+            .load()
+            .expect("YAML settings should load");
+
+        assert_settings(res);
+    }
+
+    #[test]
+    fn test_load_json5() {
+        let res = Settings::builder()
+            .file("test-data/config.json5")
+            .load()
+            .expect("JSON5 settings should load");
+
+        assert_settings(res);
+    }
+
+    #[test]
+    fn test_load_config_files() {
+        for file in [
+            "config.toml",
+            "config.yaml",
+            "config.yml",
+            "config.json5",
+            "config.json",
+        ] {
+            let path = PathBuf::from("test-data").join(file);
+            let res = load_settings_file(Some(path.clone()))
+                .unwrap_or_else(|e| panic!("{} should load: {}", file, e));
+            assert_eq!(
+                res.config_path,
+                Some(PathBuf::from("/home/user/.config/projectm").join(file))
+            );
+
+            assert_settings(res);
+        }
+    }
+
+    #[test]
+    fn test_config_file_format() {
+        let format = |path: &str| config_file_format(Path::new(path));
+        assert!(matches!(format("config.toml"), Ok(FileFormat::Toml)));
+        assert!(matches!(format("config.YML"), Ok(FileFormat::Yaml)));
+        assert!(matches!(format("config.json"), Ok(FileFormat::Json5)));
+        assert!(format("config.ini").is_err());
+        assert!(format("config").is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let toml = "frame_rate = 60\nframe_rat = 30\n";
+        assert_eq!(
+            unknown_keys(toml, &FileFormat::Toml),
+            Ok(vec![("frame_rat".to_string(), Some(2))])
+        );
+        let yaml = "# settings\n\npreset-path: /presets\nshuffle: true\n";
+        assert_eq!(
+            unknown_keys(yaml, &FileFormat::Yaml),
+            Ok(vec![("preset-path".to_string(), Some(3))])
+        );
+        let json5 = r#"{ "osd": true, "beat": 2.0 }"#;
+        assert_eq!(
+            unknown_keys(json5, &FileFormat::Json5),
+            Ok(vec![("beat".to_string(), Some(1))])
+        );
+        assert_eq!(unknown_keys("", &FileFormat::Yaml), Ok(Vec::new()));
+        assert!(unknown_keys("- a list", &FileFormat::Yaml).is_err());
+    }
+
     #[test]
     fn test_load_env_vars() {
         std::env::set_var("PM_FRAME_RATE", "60");
@@ -220,4 +953,86 @@ mod tests {
         std::env::remove_var("PM_PRESET_DURATION");
         std::env::remove_var("PM_AUDIO_INPUT");
     }
+
+    #[test]
+    fn test_path_lists() {
+        #[derive(Deserialize)]
+        struct Paths {
+            #[serde(deserialize_with = "deserialize_path_list")]
+            paths: Vec<PathBuf>,
+        }
+
+        let one: Paths = serde_json::from_str(r#"{"paths": "presets"}"#).unwrap();
+        assert_eq!(one.paths, vec![PathBuf::from("presets")]);
+        let many: Paths = serde_json::from_str(r#"{"paths": ["presets", "*.milk"]}"#).unwrap();
+        assert_eq!(
+            many.paths,
+            vec![PathBuf::from("presets"), PathBuf::from("*.milk")]
+        );
+
+        let separated = format!("community{}extra", PATH_LIST_SEPARATOR);
+        let res = Settings::try_parse_from(["test_program", "-p", "curated", "-p", &separated])
+            .expect("repeated and separated preset paths should parse");
+        assert_eq!(
+            res.preset_path,
+            Some(vec![
+                PathBuf::from("curated"),
+                PathBuf::from("community"),
+                PathBuf::from("extra")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_frames_range() {
+        assert!(Settings::try_parse_from(["test_program", "--frames", "0"]).is_err());
+        let res = Settings::try_parse_from(["test_program", "--frames", "1"]).unwrap();
+        assert_eq!(res.frames, Some(1));
+    }
+
+    #[test]
+    fn test_render_ranges() {
+        let render = |arg: &str| Cli::try_parse_from(["test_program", "render", "a.wav", arg, "0"]);
+        for arg in ["--width", "--height", "--fps"] {
+            assert!(render(arg).is_err(), "{} 0 was accepted", arg);
+        }
+        assert!(Cli::try_parse_from(["test_program", "render", "a.wav", "--fps", "30"]).is_ok());
+    }
+
+    #[test]
+    fn test_find_config_file() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        assert_eq!(find_config_file(dir), None);
+
+        fs::write(dir.join("config.json5"), "{}").unwrap();
+        assert_eq!(find_config_file(dir), Some(dir.join("config.json5")));
+        fs::write(dir.join("config.toml"), "").unwrap();
+        assert_eq!(find_config_file(dir), Some(dir.join("config.toml")));
+    }
+
+    #[test]
+    fn test_setting_sources() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let path = dir.join("config.toml");
+        fs::write(&path, "osd_duration = 5.0\nbeat_sensitivity = 1.5\n").unwrap();
+
+        let args = [
+            "test_program",
+            "--config",
+            path.to_str().unwrap(),
+            "--beat-sensitivity",
+            "2",
+        ];
+        let matches = Settings::command().try_get_matches_from(args).unwrap();
+        let cli = Settings::from_arg_matches(&matches).unwrap();
+        let loaded = load_settings(&cli, &cli_sources(&matches, &cli)).unwrap();
+
+        assert_eq!(loaded.files, vec![path.clone()]);
+        assert_eq!(loaded.settings.osd_duration, Some(5.0));
+        assert_eq!(loaded.settings.beat_sensitivity, Some(2.0));
+        assert_eq!(loaded.sources["osd_duration"], Source::File(path));
+        assert_eq!(loaded.sources["beat_sensitivity"], Source::CommandLine);
+    }
 }
@@ -0,0 +1,201 @@
+//! Config reloading: run the visualizer headlessly with a config file, change
+//! the file while it runs and check the playlist through the IPC socket.
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+/// How long to wait for the visualizer to start or apply a change.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The visualizer, killed when dropped so a failing test doesn't leave it running.
+struct Visualizer(Child);
+
+impl Drop for Visualizer {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+struct IpcClient {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl IpcClient {
+    fn connect(path: &Path) -> IpcClient {
+        let started = Instant::now();
+        loop {
+            if let Ok(stream) = UnixStream::connect(path) {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                return IpcClient { stream, reader };
+            }
+            assert!(started.elapsed() < TIMEOUT, "visualizer didn't start");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Send `request` and return its response, skipping events.
+    fn request(&mut self, request: Value) -> Value {
+        writeln!(self.stream, "{}", request).unwrap();
+        loop {
+            let mut line = String::new();
+            assert!(
+                self.reader.read_line(&mut line).unwrap() > 0,
+                "connection closed"
+            );
+            let response: Value = serde_json::from_str(&line).unwrap();
+            if response.get("event").is_none() {
+                assert_eq!(response["ok"], true, "{} failed: {}", request, response);
+                return response;
+            }
+        }
+    }
+
+    /// File names of the presets in the playlist, in order.
+    fn playlist(&mut self, dir: &Path) -> Vec<String> {
+        let path = dir.join("playlist.m3u");
+        self.request(json!({ "command": "save_playlist", "path": path }));
+        let mut presets: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .filter(|line| line.ends_with(".milk"))
+            .map(|line| {
+                Path::new(line)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        presets.sort();
+        presets
+    }
+
+    /// Wait until the playlist holds `expected`.
+    fn wait_for_playlist(&mut self, dir: &Path, expected: &[&str]) {
+        let started = Instant::now();
+        loop {
+            let playlist = self.playlist(dir);
+            if playlist == expected {
+                return;
+            }
+            assert!(
+                started.elapsed() < TIMEOUT,
+                "playlist is {:?}, expected {:?}",
+                playlist,
+                expected
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+fn write_config(path: &Path, preset_paths: &[&Path], socket: &Path) {
+    let preset_paths: Vec<String> = preset_paths
+        .iter()
+        .map(|path| format!("{:?}", path.display().to_string()))
+        .collect();
+    fs::write(
+        path,
+        format!(
+            "preset_path = [{}]\nfavorites_only = true\nipc_socket = {:?}\n",
+            preset_paths.join(", "),
+            socket.display().to_string()
+        ),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_reload_preset_path_in_favorites_only_mode() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("reload_config");
+    let _ = fs::remove_dir_all(&work_dir);
+    let preset = fs::read_to_string(root.join("test-data/presets/reactive.milk")).unwrap();
+
+    let mut preset_dirs: Vec<PathBuf> = Vec::new();
+    for dir in ["a", "b"] {
+        let dir = work_dir.join("presets").join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        preset_dirs.push(dir.canonicalize().unwrap());
+    }
+    let write_presets = |dir: &Path| {
+        let name = dir.file_name().unwrap().to_string_lossy().to_string();
+        for file in [
+            format!("favorite_{}.milk", name),
+            format!("other_{}.milk", name),
+        ] {
+            fs::write(dir.join(file), &preset).unwrap();
+        }
+    };
+    let favorites: String = preset_dirs
+        .iter()
+        .map(|dir| {
+            let name = dir.file_name().unwrap().to_string_lossy().to_string();
+            format!(
+                "{}\n",
+                dir.join(format!("favorite_{}.milk", name)).display()
+            )
+        })
+        .collect();
+    write_presets(&preset_dirs[0]);
+
+    // keep the developer's config, favorites, blacklist and statistics out of the test
+    let home_dir = work_dir.join("home");
+    let config_dir = home_dir.join("config/projectm");
+    let data_dir = home_dir.join("data/projectm");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(data_dir.join("favorites.m3u"), favorites).unwrap();
+    let config_path = config_dir.join("config.toml");
+    let socket = work_dir.join("projectm.sock");
+    write_config(&config_path, &[&preset_dirs[0]], &socket);
+
+    let _visualizer = Visualizer(
+        Command::new(env!("CARGO_BIN_EXE_projectm_sdl"))
+            .args(["--headless", "--fixed-step"])
+            .args(["--frames", "600", "--frame-rate", "10"])
+            .args(["--width", "64", "--height", "48"])
+            .args(["--audio-source", "synth:kick,bpm=120"])
+            .arg("--output-dir")
+            .arg(work_dir.join("frames"))
+            .env("XDG_CONFIG_HOME", home_dir.join("config"))
+            .env("XDG_DATA_HOME", home_dir.join("data"))
+            .env("XDG_DATA_DIRS", home_dir.join("share"))
+            // the synthesized audio needs no audio device
+            .env("SDL_AUDIO_DRIVER", "dummy")
+            .env("LIBGL_ALWAYS_SOFTWARE", "1")
+            .env("GALLIUM_DRIVER", "llvmpipe")
+            .spawn()
+            .expect("could not run projectm_sdl"),
+    );
+    let mut client = IpcClient::connect(&socket);
+    client.wait_for_playlist(&work_dir, &["favorite_a.milk"]);
+
+    // only the favorites from an added preset path play; its presets only
+    // appear now, as favorites from earlier runs play wherever they are
+    write_presets(&preset_dirs[1]);
+    write_config(&config_path, &[&preset_dirs[0], &preset_dirs[1]], &socket);
+    client.wait_for_playlist(&work_dir, &["favorite_a.milk", "favorite_b.milk"]);
+
+    // and the other presets from it come back with the whole playlist
+    client.request(json!({ "command": "set", "key": "favorites_only", "value": false }));
+    client.wait_for_playlist(
+        &work_dir,
+        &[
+            "favorite_a.milk",
+            "favorite_b.milk",
+            "other_a.milk",
+            "other_b.milk",
+        ],
+    );
+}
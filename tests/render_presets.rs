@@ -0,0 +1,273 @@
+//! Golden-image tests: render every preset in `test-data/presets` headlessly and
+//! compare the last frame against a reference image in `test-data/reference`.
+//!
+//! Rendering uses Mesa's llvmpipe software renderer, so no GPU is needed.
+//!
+//! Frames aren't bit-for-bit repeatable: libprojectM animates on the wall clock,
+//! smooths the bass, mid and treble levels over the time between frames and seeds
+//! its hue shading randomly, so even a still preset differs slightly between runs.
+//! Rendering at a low frame rate keeps the frame intervals at the paced frame
+//! time, as llvmpipe draws a frame well within it. Across 60 runs of
+//! `reactive.milk`, 20 of them with another process competing for the only CPU,
+//! half of all pairs of last frames matched in every block and the worst pair
+//! differed in 3.6% of blocks, so the tolerance below allows about twice that.
+//! A missing reference image fails the test; set `UPDATE_REFERENCE_IMAGES=1` to
+//! write them from the current output, for new presets or after an intended change.
+//! On a mismatch the rendered frame and a diff image are written next to the
+//! test's temporary output.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const FRAMES: u32 = 30;
+/// Low enough that frames are paced rather than rendered back to back.
+const FRAME_RATE: u32 = 10;
+
+/// Size of the square blocks compared by averaged color.
+const BLOCK_SIZE: u32 = 8;
+/// Largest difference (0-255) in a block's average color that still counts as a match.
+const BLOCK_TOLERANCE: f64 = 16.0;
+/// Fraction of blocks allowed to differ before the images count as different.
+const MAX_MISMATCHED_BLOCKS: f64 = 0.08;
+
+struct Image {
+    width: u32,
+    height: u32,
+    /// Top-down RGBA rows.
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn load(path: &Path) -> Image {
+        let file = fs::File::open(path)
+            .unwrap_or_else(|e| panic!("could not open {}: {}", path.display(), e));
+        let mut reader = png::Decoder::new(file)
+            .read_info()
+            .unwrap_or_else(|e| panic!("could not decode {}: {}", path.display(), e));
+
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(
+            (info.color_type, info.bit_depth),
+            (png::ColorType::Rgba, png::BitDepth::Eight),
+            "{} is not an 8-bit RGBA image",
+            path.display()
+        );
+        pixels.truncate(info.buffer_size());
+
+        Image {
+            width: info.width,
+            height: info.height,
+            pixels,
+        }
+    }
+
+    fn save(&self, path: &Path) {
+        let file = fs::File::create(path)
+            .unwrap_or_else(|e| panic!("could not create {}: {}", path.display(), e));
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .unwrap_or_else(|e| panic!("could not write {}: {}", path.display(), e));
+    }
+
+    /// Average RGB color of the block with its top left corner at `x`, `y`.
+    fn block_average(&self, x: u32, y: u32) -> [f64; 3] {
+        let mut sum = [0.0; 3];
+        let mut count = 0.0;
+        for py in y..(y + BLOCK_SIZE).min(self.height) {
+            for px in x..(x + BLOCK_SIZE).min(self.width) {
+                let offset = ((py * self.width + px) * 4) as usize;
+                for (channel, total) in sum.iter_mut().enumerate() {
+                    *total += self.pixels[offset + channel] as f64;
+                }
+                count += 1.0;
+            }
+        }
+        sum.map(|total| total / count)
+    }
+}
+
+/// Compare images block by block, returning the fraction of blocks whose
+/// average color differs by more than the tolerance.
+///
+/// Averaging over blocks ignores single-pixel noise from rasterization and
+/// timing differences while still catching missing or broken shapes, colors
+/// and textures.
+fn mismatched_blocks(actual: &Image, expected: &Image) -> f64 {
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "image sizes differ"
+    );
+
+    let mut blocks = 0;
+    let mut mismatched = 0;
+    for y in (0..actual.height).step_by(BLOCK_SIZE as usize) {
+        for x in (0..actual.width).step_by(BLOCK_SIZE as usize) {
+            let a = actual.block_average(x, y);
+            let b = expected.block_average(x, y);
+            let difference = (0..3).map(|c| (a[c] - b[c]).abs()).fold(0.0, f64::max);
+            if difference > BLOCK_TOLERANCE {
+                mismatched += 1;
+            }
+            blocks += 1;
+        }
+    }
+
+    mismatched as f64 / blocks as f64
+}
+
+/// Per-pixel absolute difference, amplified to make small changes visible.
+fn diff_image(actual: &Image, expected: &Image) -> Image {
+    let pixels = actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+        .flat_map(|(a, b)| {
+            let diff = |c: usize| (a[c].abs_diff(b[c]) as u32 * 4).min(255) as u8;
+            [diff(0), diff(1), diff(2), 255]
+        })
+        .collect();
+
+    Image {
+        width: actual.width,
+        height: actual.height,
+        pixels,
+    }
+}
+
+/// Render `preset` headlessly and return the path of the last frame.
+fn render_preset(preset: &Path, work_dir: &Path) -> PathBuf {
+    // the playlist takes directories, so give each preset one of its own
+    let preset_dir = work_dir.join("preset");
+    let output_dir = work_dir.join("frames");
+    let _ = fs::remove_dir_all(work_dir);
+    fs::create_dir_all(&preset_dir).unwrap();
+    fs::copy(preset, preset_dir.join(preset.file_name().unwrap())).unwrap();
+    // keep the developer's config, favorites, blacklist and statistics out of the test
+    let home_dir = work_dir.join("home");
+    fs::create_dir_all(&home_dir).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_projectm_sdl"))
+        .args(["--headless", "--fixed-step"])
+        .args(["--frames", &FRAMES.to_string()])
+        .args(["--frame-rate", &FRAME_RATE.to_string()])
+        .args([
+            "--width",
+            &WIDTH.to_string(),
+            "--height",
+            &HEIGHT.to_string(),
+        ])
+        .args(["--audio-source", "synth:kick,bpm=120"])
+        .arg("--preset-path")
+        .arg(&preset_dir)
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .env("XDG_CONFIG_HOME", home_dir.join("config"))
+        .env("XDG_DATA_HOME", home_dir.join("data"))
+        .env("XDG_DATA_DIRS", home_dir.join("share"))
+        // the synthesized audio needs no audio device
+        .env("SDL_AUDIO_DRIVER", "dummy")
+        .env("LIBGL_ALWAYS_SOFTWARE", "1")
+        .env("GALLIUM_DRIVER", "llvmpipe")
+        .status()
+        .expect("could not run projectm_sdl");
+    assert!(status.success(), "rendering {} failed", preset.display());
+
+    output_dir.join(format!("frame_{:05}.png", FRAMES - 1))
+}
+
+#[test]
+fn test_presets_match_reference_images() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference_dir = root.join("test-data/reference");
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("render_presets");
+    let update = std::env::var_os("UPDATE_REFERENCE_IMAGES").is_some();
+
+    let mut presets: Vec<PathBuf> = fs::read_dir(root.join("test-data/presets"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "milk"))
+        .collect();
+    presets.sort();
+    assert!(!presets.is_empty(), "no presets to test");
+
+    let mut failures = Vec::new();
+    for preset in &presets {
+        let name = preset.file_stem().unwrap().to_string_lossy().to_string();
+        let frame_path = render_preset(preset, &work_dir.join(&name));
+        let reference_path = reference_dir.join(format!("{}.png", name));
+
+        if update {
+            fs::create_dir_all(&reference_dir).unwrap();
+            fs::copy(&frame_path, &reference_path).unwrap();
+            println!("{}: wrote reference image", name);
+            continue;
+        }
+        if !reference_path.exists() {
+            failures.push(format!(
+                "{}: no reference image {}, run with UPDATE_REFERENCE_IMAGES=1 to create it",
+                name,
+                reference_path.display()
+            ));
+            continue;
+        }
+
+        let actual = Image::load(&frame_path);
+        let expected = Image::load(&reference_path);
+        let mismatched = mismatched_blocks(&actual, &expected);
+        println!("{}: {:.1}% of blocks differ", name, mismatched * 100.0);
+
+        if mismatched > MAX_MISMATCHED_BLOCKS {
+            let actual_path = work_dir.join(format!("{}-actual.png", name));
+            let diff_path = work_dir.join(format!("{}-diff.png", name));
+            actual.save(&actual_path);
+            diff_image(&actual, &expected).save(&diff_path);
+            failures.push(format!(
+                "{}: {:.1}% of blocks differ (see {} and {})",
+                name,
+                mismatched * 100.0,
+                actual_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "presets differ from their reference images:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_mismatched_blocks() {
+    let gray = |value: u8| Image {
+        width: 32,
+        height: 32,
+        pixels: [value, value, value, 255].repeat(32 * 32),
+    };
+
+    let expected = gray(100);
+    assert_eq!(mismatched_blocks(&gray(100), &expected), 0.0);
+    // small differences are within tolerance
+    assert_eq!(mismatched_blocks(&gray(110), &expected), 0.0);
+    assert_eq!(mismatched_blocks(&gray(200), &expected), 1.0);
+
+    // a single changed block out of 16
+    let mut actual = gray(100);
+    for y in 0..8 {
+        for x in 0..8 {
+            let offset = (y * 32 + x) * 4;
+            actual.pixels[offset..offset + 3].copy_from_slice(&[255, 0, 0]);
+        }
+    }
+    assert_eq!(mismatched_blocks(&actual, &expected), 1.0 / 16.0);
+}